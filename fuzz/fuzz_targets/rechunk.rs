@@ -0,0 +1,46 @@
+//! Feed arbitrary splits of arbitrary input through a `Rechunker`.
+//!
+//! The first byte picks the chunk size, the second the chunk count (255 for
+//! no limit). The rest is a sequence of pieces, each a length byte followed
+//! by that many bytes.
+#![no_main]
+
+use bytes::Bytes;
+use libfuzzer_sys::fuzz_target;
+use vl_aws_util::chunk::Rechunker;
+
+fuzz_target!(|data: &[u8]| {
+    let [chunk_size, count, rest @ ..] = data else {
+        return;
+    };
+    let chunk_size = *chunk_size as usize % 16 + 1;
+    let count = (*count != 255).then_some(*count as usize);
+
+    let mut pieces = Vec::new();
+    let mut rest = rest;
+    while let [len, tail @ ..] = rest {
+        let len = (*len as usize).min(tail.len());
+        pieces.push(&tail[..len]);
+        rest = &tail[len..];
+    }
+    let input = pieces.concat();
+
+    let mut rechunker = Rechunker::new(chunk_size, count);
+    let mut output = Vec::new();
+    for piece in pieces {
+        rechunker.push(Bytes::copy_from_slice(piece));
+        while let Some(chunk) = rechunker.next_chunk() {
+            assert_eq!(chunk.len(), chunk_size);
+            output.extend_from_slice(&chunk);
+        }
+    }
+
+    let whole = input.len() / chunk_size;
+    let expected = count.map_or(whole, |c| c.min(whole));
+    assert_eq!(output, &input[..expected * chunk_size]);
+    let done = count == Some(expected);
+    assert_eq!(
+        rechunker.finish().is_ok(),
+        done || input.len() % chunk_size == 0
+    );
+});
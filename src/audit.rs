@@ -0,0 +1,250 @@
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use aws_sdk_s3::{error::SdkError, operation::put_object::PutObjectError, Client};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::{fs::File, io::AsyncWriteExt, sync::Mutex};
+
+/// Caller-supplied context attached to every audit event.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct AuditContext {
+    pub task_id: Option<String>,
+    pub tenant: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum AuditOperation {
+    Put {
+        key: String,
+        size: usize,
+    },
+    CreateMultipartUpload {
+        key: String,
+        upload_id: Option<String>,
+    },
+    CompleteMultipartUpload {
+        key: String,
+        upload_id: String,
+        parts: usize,
+        uploaded_bytes: usize,
+    },
+    AbortMultipartUpload {
+        key: String,
+        upload_id: String,
+    },
+    Delete {
+        key: String,
+    },
+    Copy {
+        source_bucket: String,
+        source_key: String,
+        key: String,
+    },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AuditEvent {
+    /// Milliseconds since the unix epoch.
+    pub timestamp: u64,
+    pub bucket: String,
+    #[serde(flatten)]
+    pub operation: AuditOperation,
+    #[serde(flatten)]
+    pub context: AuditContext,
+    /// The error message if the operation failed.
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Error)]
+pub enum AuditError {
+    #[error("could not serialize audit event: {0}")]
+    Serialize(#[from] serde_json::Error),
+    #[error("could not write audit log: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not put audit event: {0}")]
+    Put(#[from] SdkError<PutObjectError>),
+}
+
+#[async_trait]
+pub trait AuditSink: Send + Sync {
+    async fn record(&self, event: &AuditEvent) -> Result<(), AuditError>;
+}
+
+/// Appends events as json lines to a local file.
+pub struct JsonlAuditSink {
+    file: Mutex<File>,
+}
+
+impl JsonlAuditSink {
+    pub async fn open<P: AsRef<Path>>(path: P) -> Result<Self, AuditError> {
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+}
+
+#[async_trait]
+impl AuditSink for JsonlAuditSink {
+    async fn record(&self, event: &AuditEvent) -> Result<(), AuditError> {
+        let mut line = serde_json::to_vec(event)?;
+        line.push(b'\n');
+        let mut file = self.file.lock().await;
+        file.write_all(&line).await?;
+        file.flush().await?;
+
+        Ok(())
+    }
+}
+
+/// Writes every event as its own object under a prefix.
+///
+/// S3 has no append, so the log is the set of objects under the prefix.
+/// Keys start with the zero-padded timestamp so that listing them returns
+/// events in order.
+pub struct S3AuditSink {
+    client: Arc<Client>,
+    bucket: String,
+    prefix: String,
+    sequence: AtomicU64,
+}
+
+impl S3AuditSink {
+    pub fn new(client: Arc<Client>, bucket: String, prefix: String) -> Self {
+        Self {
+            client,
+            bucket,
+            prefix,
+            sequence: AtomicU64::new(0),
+        }
+    }
+}
+
+#[async_trait]
+impl AuditSink for S3AuditSink {
+    async fn record(&self, event: &AuditEvent) -> Result<(), AuditError> {
+        let body = serde_json::to_vec(event)?;
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+        let key = format!(
+            "{}{:020}-{}-{sequence:08}.json",
+            self.prefix,
+            event.timestamp,
+            std::process::id()
+        );
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .body(body.into())
+            .send()
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// Hands every event to a callback.
+pub struct CallbackAuditSink<F> {
+    callback: F,
+}
+
+impl<F: Fn(&AuditEvent) + Send + Sync> CallbackAuditSink<F> {
+    pub fn new(callback: F) -> Self {
+        Self { callback }
+    }
+}
+
+#[async_trait]
+impl<F: Fn(&AuditEvent) + Send + Sync> AuditSink for CallbackAuditSink<F> {
+    async fn record(&self, event: &AuditEvent) -> Result<(), AuditError> {
+        (self.callback)(event);
+
+        Ok(())
+    }
+}
+
+/// Called with every event that could not be written to the sink.
+pub type AuditFailureHandler = Arc<dyn Fn(&AuditEvent, &AuditError) + Send + Sync>;
+
+/// A sink together with the context to record events under.
+#[derive(Clone)]
+pub struct Auditor {
+    sink: Arc<dyn AuditSink>,
+    context: AuditContext,
+    on_failure: Option<AuditFailureHandler>,
+}
+
+impl Auditor {
+    pub fn new(sink: Arc<dyn AuditSink>, context: AuditContext) -> Self {
+        Self {
+            sink,
+            context,
+            on_failure: None,
+        }
+    }
+
+    /// Hand events the sink failed to write to `handler`, for instance to
+    /// write them somewhere else or to stop taking writes, rather than
+    /// printing them to stderr.
+    pub fn with_failure_handler(mut self, handler: AuditFailureHandler) -> Self {
+        self.on_failure = Some(handler);
+        self
+    }
+
+    pub fn context(&self) -> &AuditContext {
+        &self.context
+    }
+
+    /// The same sink, recording under a different context.
+    pub fn with_context(&self, context: AuditContext) -> Self {
+        Self {
+            sink: self.sink.clone(),
+            context,
+            on_failure: self.on_failure.clone(),
+        }
+    }
+
+    /// Records an operation along with its outcome.
+    ///
+    /// Failing to write the audit log does not fail the operation itself.
+    /// The event is passed to the failure handler instead, or printed to
+    /// stderr if there is none.
+    pub async fn record<T, E: std::fmt::Display>(
+        &self,
+        bucket: &str,
+        operation: AuditOperation,
+        result: &Result<T, E>,
+    ) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let event = AuditEvent {
+            timestamp,
+            bucket: bucket.to_string(),
+            operation,
+            context: self.context.clone(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        };
+        if let Err(e) = self.sink.record(&event).await {
+            match self.on_failure.as_ref() {
+                Some(on_failure) => on_failure(&event, &e),
+                None => eprintln!("audit log failed: {e}. event: {event:?}"),
+            }
+        }
+    }
+}
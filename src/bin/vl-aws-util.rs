@@ -0,0 +1,50 @@
+use std::process::ExitCode;
+
+use vl_aws_util::{client::default_client, migrate::migrate_prefix};
+
+const USAGE: &str = "usage: vl-aws-util migrate-upload-info <bucket> <prefix> [--dry-run]";
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let dry_run = args.iter().any(|a| a == "--dry-run");
+    let positional: Vec<&str> = args
+        .iter()
+        .filter(|a| *a != "--dry-run")
+        .map(String::as_str)
+        .collect();
+
+    let ["migrate-upload-info", bucket, prefix] = positional[..] else {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    };
+
+    let client = default_client().await;
+    let report = match migrate_prefix(&client, bucket, prefix, dry_run).await {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let verb = if dry_run { "would migrate" } else { "migrated" };
+    for key in &report.migrated {
+        println!("{verb} {key}");
+    }
+    for (key, reason) in &report.failed {
+        eprintln!("failed {key}: {reason}");
+    }
+    println!(
+        "{} {verb}, {} already current, {} failed",
+        report.migrated.len(),
+        report.current.len(),
+        report.failed.len()
+    );
+
+    if report.failed.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
@@ -0,0 +1,117 @@
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use async_stream::stream;
+use bytes::Bytes;
+use futures::stream::StreamExt;
+use futures::Stream;
+use thiserror::Error;
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+/// What to do when a consumer's buffer is full.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SlowConsumerPolicy {
+    /// Hold back every consumer until the slowest one catches up. Every
+    /// consumer sees every chunk.
+    #[default]
+    Wait,
+    /// Disconnect a consumer that falls a full buffer behind, so the others
+    /// can go on. The disconnected consumer gets a final `Lagged` error.
+    Disconnect,
+}
+
+#[derive(Debug, Error)]
+pub enum BroadcastError<E> {
+    #[error(transparent)]
+    Source(Arc<E>),
+    #[error("consumer fell too far behind and was disconnected")]
+    Lagged,
+}
+
+impl<E> Clone for BroadcastError<E> {
+    fn clone(&self) -> Self {
+        match self {
+            Self::Source(e) => Self::Source(e.clone()),
+            Self::Lagged => Self::Lagged,
+        }
+    }
+}
+
+struct Consumer<E> {
+    tx: mpsc::Sender<Result<Bytes, BroadcastError<E>>>,
+    lagged: Arc<AtomicBool>,
+}
+
+fn consumer_stream<E>(
+    mut rx: mpsc::Receiver<Result<Bytes, BroadcastError<E>>>,
+    lagged: Arc<AtomicBool>,
+) -> impl Stream<Item = Result<Bytes, BroadcastError<E>>> {
+    stream! {
+        while let Some(item) = rx.recv().await {
+            yield item;
+        }
+        if lagged.load(Ordering::Acquire) {
+            yield Err(BroadcastError::Lagged);
+        }
+    }
+}
+
+/// Drive a single chunk stream, such as one from `stream_vecs_from`, and
+/// hand every chunk to `consumers` streams.
+///
+/// Each consumer has its own buffer of `buffer` chunks. Chunks are shared
+/// rather than copied. The source is read until it ends, fails, or every
+/// consumer is dropped.
+pub fn broadcast_chunks<S, E>(
+    source: S,
+    consumers: usize,
+    buffer: usize,
+    policy: SlowConsumerPolicy,
+) -> Vec<impl Stream<Item = Result<Bytes, BroadcastError<E>>>>
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: Send + Sync + 'static,
+{
+    let mut senders = Vec::with_capacity(consumers);
+    let mut streams = Vec::with_capacity(consumers);
+    for _ in 0..consumers {
+        let (tx, rx) = mpsc::channel(buffer.max(1));
+        let lagged = Arc::new(AtomicBool::new(false));
+        streams.push(consumer_stream(rx, lagged.clone()));
+        senders.push(Consumer { tx, lagged });
+    }
+
+    tokio::spawn(async move {
+        let mut source = pin!(source);
+        while let Some(item) = source.next().await {
+            let is_last = item.is_err();
+            let item = item.map_err(|e| BroadcastError::Source(Arc::new(e)));
+
+            let mut remaining = Vec::with_capacity(senders.len());
+            for consumer in senders {
+                match policy {
+                    SlowConsumerPolicy::Wait => {
+                        if consumer.tx.send(item.clone()).await.is_ok() {
+                            remaining.push(consumer);
+                        }
+                    }
+                    SlowConsumerPolicy::Disconnect => match consumer.tx.try_send(item.clone()) {
+                        Ok(()) => remaining.push(consumer),
+                        Err(TrySendError::Full(_)) => {
+                            consumer.lagged.store(true, Ordering::Release);
+                        }
+                        Err(TrySendError::Closed(_)) => {}
+                    },
+                }
+            }
+            senders = remaining;
+
+            if is_last || senders.is_empty() {
+                break;
+            }
+        }
+    });
+
+    streams
+}
@@ -0,0 +1,71 @@
+use bytes::{Bytes, BytesMut};
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("input ended with a partial chunk of {len} bytes (chunk size {chunk_size})")]
+pub struct PartialChunk {
+    pub len: usize,
+    pub chunk_size: usize,
+}
+
+/// Reassembles arbitrarily split input into chunks of a fixed size.
+///
+/// This is the framing logic behind `stream_vecs`, without any IO. Feed it
+/// input with `push`, take whole chunks out with `next_chunk`, and call
+/// `finish` once the input has ended.
+#[derive(Debug)]
+pub struct Rechunker {
+    chunk_size: usize,
+    remaining: Option<usize>,
+    buf: BytesMut,
+}
+
+impl Rechunker {
+    /// Produce chunks of `chunk_size` bytes, stopping after `count` chunks
+    /// if given.
+    pub fn new(chunk_size: usize, count: Option<usize>) -> Self {
+        assert!(chunk_size > 0, "chunk size must be positive");
+        Self {
+            chunk_size,
+            remaining: count,
+            buf: BytesMut::new(),
+        }
+    }
+
+    pub fn push(&mut self, data: Bytes) {
+        if !self.is_done() {
+            self.buf.extend(data);
+        }
+    }
+
+    pub fn next_chunk(&mut self) -> Option<Bytes> {
+        if self.is_done() || self.buf.len() < self.chunk_size {
+            return None;
+        }
+
+        if let Some(remaining) = self.remaining.as_mut() {
+            *remaining -= 1;
+        }
+        Some(self.buf.split_to(self.chunk_size).freeze())
+    }
+
+    /// Whether `count` chunks have been produced, so no more input is
+    /// needed.
+    pub fn is_done(&self) -> bool {
+        self.remaining == Some(0)
+    }
+
+    /// Check that the input ended on a chunk boundary.
+    ///
+    /// Once done, anything left over is ignored.
+    pub fn finish(&self) -> Result<(), PartialChunk> {
+        if self.is_done() || self.buf.is_empty() {
+            Ok(())
+        } else {
+            Err(PartialChunk {
+                len: self.buf.len(),
+                chunk_size: self.chunk_size,
+            })
+        }
+    }
+}
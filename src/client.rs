@@ -1,11 +1,98 @@
+use std::time::Duration;
+
 use aws_config::BehaviorVersion;
-use aws_sdk_s3::config::StalledStreamProtectionConfig;
+use aws_sdk_s3::config::{Credentials, Region, SharedHttpClient, StalledStreamProtectionConfig};
+
+#[cfg(feature = "replay")]
+use crate::replay::{self, Recorder, ReplayError};
+
+/// How the sdk should treat streams that stop making progress.
+///
+/// A stalled download surfaces as a read error on the body, which the
+/// download functions in this crate retry from where they left off.
+#[derive(Clone, Debug, Default)]
+pub enum StalledStreamProtection {
+    /// Never consider a stream stalled.
+    #[default]
+    Disabled,
+    /// Fail uploads and downloads that make no progress for `grace_period`.
+    Enabled { grace_period: Duration },
+    /// Like `Enabled`, but only for downloads. Uploads from a slow producer
+    /// look stalled to the sdk, so this is often the better choice.
+    DownloadsOnly { grace_period: Duration },
+}
+
+impl StalledStreamProtection {
+    fn to_config(&self) -> StalledStreamProtectionConfig {
+        match self {
+            Self::Disabled => StalledStreamProtectionConfig::disabled(),
+            Self::Enabled { grace_period } => StalledStreamProtectionConfig::enabled()
+                .grace_period(*grace_period)
+                .build(),
+            Self::DownloadsOnly { grace_period } => StalledStreamProtectionConfig::enabled()
+                .upload_enabled(false)
+                .download_enabled(true)
+                .grace_period(*grace_period)
+                .build(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ClientBuilder {
+    stalled_stream_protection: StalledStreamProtection,
+    http_client: Option<SharedHttpClient>,
+    offline: bool,
+}
+
+impl ClientBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn stalled_stream_protection(mut self, protection: StalledStreamProtection) -> Self {
+        self.stalled_stream_protection = protection;
+        self
+    }
+
+    /// Record all traffic of the client into `recorder`.
+    #[cfg(feature = "replay")]
+    pub fn record(mut self, recorder: &Recorder) -> Self {
+        self.http_client = Some(SharedHttpClient::new(recorder.clone()));
+        self.offline = false;
+        self
+    }
+
+    /// Answer all requests from a recording instead of the network.
+    ///
+    /// Credentials and region don't need to be configured for this, the
+    /// region is always us-east-1.
+    #[cfg(feature = "replay")]
+    pub fn replay(mut self, path: impl AsRef<std::path::Path>) -> Result<Self, ReplayError> {
+        self.http_client = Some(SharedHttpClient::new(replay::load_recording(path)?));
+        self.offline = true;
+        Ok(self)
+    }
+
+    pub async fn build(self) -> aws_sdk_s3::Client {
+        let mut loader = aws_config::defaults(BehaviorVersion::latest());
+        if self.offline {
+            // requests are still signed, but nothing checks the signatures
+            loader = loader
+                .credentials_provider(Credentials::new("replay", "replay", None, None, "replay"))
+                .region(Region::new("us-east-1"));
+        }
+        let config = loader.load().await;
+        let mut config = config
+            .into_builder()
+            .stalled_stream_protection(self.stalled_stream_protection.to_config());
+        if let Some(http_client) = self.http_client {
+            config = config.http_client(http_client);
+        }
+        aws_sdk_s3::Client::new(&config.build())
+    }
+}
 
 pub async fn default_client() -> aws_sdk_s3::Client {
-    let config = aws_config::load_defaults(BehaviorVersion::latest()).await;
-    let config = config
-        .into_builder()
-        .stalled_stream_protection(StalledStreamProtectionConfig::disabled())
-        .build();
-    aws_sdk_s3::Client::new(&config)
+    ClientBuilder::new().build().await
 }
@@ -0,0 +1,240 @@
+use std::{
+    collections::HashMap,
+    io::Read,
+    sync::{Arc, Mutex},
+};
+
+use aws_sdk_s3::Client;
+use bytes::Bytes;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::{
+    download::DownloadVecError,
+    names::NameError,
+    object::ObjectError,
+    options::OpOptions,
+    store::{S3Store, StorePutError},
+};
+
+/// Object metadata recording which dictionary an object was compressed with.
+pub const DICTIONARY_METADATA: &str = "zstd-dictionary";
+
+#[derive(Debug, Error)]
+pub enum CompressionError {
+    #[error(transparent)]
+    InvalidKey(#[from] NameError),
+    #[error(transparent)]
+    Object(#[from] ObjectError),
+    #[error(transparent)]
+    Download(#[from] DownloadVecError),
+    #[error(transparent)]
+    Put(#[from] StorePutError),
+    #[error("dictionary {0} does not exist")]
+    DictionaryNotFound(String),
+    #[error("dictionary stored as {expected} hashes to {actual}")]
+    DictionaryMismatch { expected: String, actual: String },
+    #[error("zstd failed: {0}")]
+    Zstd(#[from] std::io::Error),
+}
+
+/// Train a dictionary on typical records, such as a batch of small metadata
+/// objects.
+pub fn train_dictionary<S: AsRef<[u8]>>(
+    samples: &[S],
+    max_size: usize,
+) -> Result<Vec<u8>, CompressionError> {
+    Ok(zstd::dict::from_samples(samples, max_size)?)
+}
+
+/// A zstd dictionary, identified by a hash of its contents.
+#[derive(Clone, Debug)]
+pub struct Dictionary {
+    pub id: String,
+    pub data: Arc<Vec<u8>>,
+}
+
+impl Dictionary {
+    pub fn new(data: Vec<u8>) -> Self {
+        let digest = Sha256::digest(&data);
+        let id = digest[..16].iter().map(|b| format!("{b:02x}")).collect();
+        Self {
+            id,
+            data: Arc::new(data),
+        }
+    }
+}
+
+/// Dictionaries stored as objects under a prefix of a store.
+///
+/// Fetched dictionaries are cached, so readers only download each one once.
+pub struct DictionaryStore {
+    store: S3Store,
+    prefix: String,
+    cache: Mutex<HashMap<String, Dictionary>>,
+}
+
+impl DictionaryStore {
+    pub fn new(store: S3Store, prefix: String) -> Self {
+        Self {
+            store,
+            prefix,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn dictionary_key(&self, id: &str) -> String {
+        format!("{}{id}", self.prefix)
+    }
+
+    pub async fn save(&self, dictionary: &Dictionary) -> Result<(), CompressionError> {
+        self.store
+            .put_object(
+                self.dictionary_key(&dictionary.id),
+                Bytes::from(dictionary.data.to_vec()),
+            )
+            .await?;
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(dictionary.id.clone(), dictionary.clone());
+
+        Ok(())
+    }
+
+    /// Fetch a dictionary, checking that its contents match its id.
+    pub async fn fetch(&self, id: &str) -> Result<Dictionary, CompressionError> {
+        if let Some(dictionary) = self.cache.lock().unwrap().get(id) {
+            return Ok(dictionary.clone());
+        }
+
+        let data = self
+            .store
+            .try_download_vec::<u8>(&self.dictionary_key(id))
+            .await?
+            .ok_or_else(|| CompressionError::DictionaryNotFound(id.to_string()))?;
+        let dictionary = Dictionary::new(data);
+        if dictionary.id != id {
+            return Err(CompressionError::DictionaryMismatch {
+                expected: id.to_string(),
+                actual: dictionary.id,
+            });
+        }
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(id.to_string(), dictionary.clone());
+
+        Ok(dictionary)
+    }
+}
+
+/// Compress `data`, with `dictionary` if given.
+pub fn compress(
+    data: &[u8],
+    dictionary: Option<&Dictionary>,
+    level: i32,
+) -> Result<Vec<u8>, CompressionError> {
+    Ok(match dictionary {
+        Some(dictionary) => {
+            zstd::bulk::Compressor::with_dictionary(level, &dictionary.data)?.compress(data)?
+        }
+        None => zstd::bulk::compress(data, level)?,
+    })
+}
+
+/// Decompress data compressed by `compress` with the same dictionary.
+pub fn decompress(
+    compressed: &[u8],
+    dictionary: Option<&Dictionary>,
+) -> Result<Vec<u8>, CompressionError> {
+    let mut data = Vec::new();
+    match dictionary {
+        Some(dictionary) => {
+            zstd::stream::read::Decoder::with_dictionary(compressed, &dictionary.data)?
+                .read_to_end(&mut data)?;
+        }
+        None => {
+            zstd::stream::read::Decoder::new(compressed)?.read_to_end(&mut data)?;
+        }
+    }
+
+    Ok(data)
+}
+
+/// The object metadata recording that an object was compressed with
+/// `dictionary`.
+pub fn dictionary_metadata(dictionary: Option<&Dictionary>) -> Option<HashMap<String, String>> {
+    dictionary.map(|d| HashMap::from([(DICTIONARY_METADATA.to_string(), d.id.clone())]))
+}
+
+/// Compress `data` and store it, recording the dictionary used (if any) in
+/// the object metadata.
+pub async fn put_compressed(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    data: &[u8],
+    dictionary: Option<&Dictionary>,
+    level: i32,
+) -> Result<(), CompressionError> {
+    let compressed = compress(data, dictionary, level)?;
+    client
+        .put_object()
+        .bucket(bucket)
+        .key(key)
+        .set_metadata(dictionary_metadata(dictionary))
+        .body(compressed.into())
+        .send()
+        .await
+        .map_err(ObjectError::from)?;
+
+    Ok(())
+}
+
+/// Fetch and decompress an object written by `put_compressed`, fetching its
+/// dictionary from `dictionaries` if it was compressed with one.
+pub async fn get_decompressed(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    dictionaries: &DictionaryStore,
+) -> Result<Vec<u8>, CompressionError> {
+    get_decompressed_with_options(client, bucket, key, dictionaries, &OpOptions::default()).await
+}
+
+pub async fn get_decompressed_with_options(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    dictionaries: &DictionaryStore,
+    options: &OpOptions,
+) -> Result<Vec<u8>, CompressionError> {
+    let o = client
+        .get_object()
+        .bucket(bucket)
+        .key(key)
+        .customize()
+        .config_override(options.config_override())
+        .send()
+        .await
+        .map_err(ObjectError::from)?;
+    let dictionary_id = o
+        .metadata()
+        .and_then(|m| m.get(DICTIONARY_METADATA))
+        .cloned();
+    let compressed = o
+        .body
+        .collect()
+        .await
+        .map_err(ObjectError::from)?
+        .into_bytes();
+
+    match dictionary_id {
+        Some(id) => {
+            let dictionary = dictionaries.fetch(&id).await?;
+            decompress(&compressed, Some(&dictionary))
+        }
+        None => decompress(&compressed, None),
+    }
+}
@@ -3,49 +3,183 @@ use std::sync::Arc;
 
 use async_stream::stream;
 use aws_sdk_s3::error::SdkError;
-use aws_sdk_s3::operation::get_object::GetObjectError;
-use aws_sdk_s3::primitives::{ByteStream, ByteStreamError};
-use bytes::{Bytes, BytesMut};
+use aws_sdk_s3::operation::get_bucket_versioning::GetBucketVersioningError;
+use aws_sdk_s3::operation::get_object::{GetObjectError, GetObjectOutput};
+use aws_sdk_s3::operation::head_object::{HeadObjectError, HeadObjectOutput};
+use aws_sdk_s3::operation::list_object_versions::ListObjectVersionsError;
+use aws_sdk_s3::primitives::{ByteStream, ByteStreamError, DateTime};
+use bytes::Bytes;
 use futures::stream::StreamExt;
 use futures::Stream;
 use thiserror::Error;
 use tokio_stream::wrappers::ReceiverStream;
 
+use crate::chunk::{PartialChunk, Rechunker};
+use crate::names::{NameError, ObjectKey};
+use crate::options::OpOptions;
+use crate::pool::{BufferPool, Pooled};
+
+#[derive(Debug, Error)]
+pub enum DownloadVecError {
+    #[error(transparent)]
+    InvalidKey(#[from] NameError),
+    #[error("no such key: {0}")]
+    NotFound(String),
+    #[error(transparent)]
+    RequestFailed(#[from] aws_sdk_s3::Error),
+    #[error("read failed: {0}")]
+    ReadFailed(#[from] ByteStreamError),
+    #[error("body of {key} had {received} bytes, expected {expected}")]
+    LengthMismatch {
+        key: String,
+        expected: usize,
+        received: usize,
+    },
+    #[error("size {size} of {key} is not a multiple of the element size {element_size}")]
+    UnalignedSize {
+        key: String,
+        size: usize,
+        element_size: usize,
+    },
+    #[error("no content length was returned for {0}")]
+    NoContentLength(String),
+    #[error("cannot download into a vector of zero-sized elements")]
+    ZeroSizedElement,
+}
+
 pub async fn download_vec<T: Copy + Default>(
     client: &aws_sdk_s3::Client,
     bucket: &str,
     key: &str,
-) -> Result<Option<Vec<T>>, aws_sdk_s3::Error> {
-    let result = client.get_object().bucket(bucket).key(key).send().await;
+) -> Result<Vec<T>, DownloadVecError> {
+    download_vec_with_options(client, bucket, key, &OpOptions::default()).await
+}
+
+/// Like `download_vec`, but returns `None` if the object doesn't exist.
+pub async fn try_download_vec<T: Copy + Default>(
+    client: &aws_sdk_s3::Client,
+    bucket: &str,
+    key: &str,
+) -> Result<Option<Vec<T>>, DownloadVecError> {
+    try_download_vec_with_options(client, bucket, key, &OpOptions::default()).await
+}
+
+pub async fn try_download_vec_with_options<T: Copy + Default>(
+    client: &aws_sdk_s3::Client,
+    bucket: &str,
+    key: &str,
+    options: &OpOptions,
+) -> Result<Option<Vec<T>>, DownloadVecError> {
+    match download_vec_with_options(client, bucket, key, options).await {
+        Err(DownloadVecError::NotFound(_)) => Ok(None),
+        result => result.map(Some),
+    }
+}
+
+pub async fn download_vec_with_options<T: Copy + Default>(
+    client: &aws_sdk_s3::Client,
+    bucket: &str,
+    key: &str,
+    options: &OpOptions,
+) -> Result<Vec<T>, DownloadVecError> {
+    ObjectKey::validate(key)?;
+    let size_of_t = std::mem::size_of::<T>();
+    if size_of_t == 0 {
+        return Err(DownloadVecError::ZeroSizedElement);
+    }
+    let result = client
+        .get_object()
+        .bucket(bucket)
+        .key(key)
+        .customize()
+        .config_override(options.config_override())
+        .send()
+        .await;
 
     match result {
         Ok(o) => {
             let mut stream = o.body;
-            let size = o.content_length.unwrap() as usize;
-            let size_of_t = std::mem::size_of::<T>();
+            let Some(size) = o.content_length else {
+                return Err(DownloadVecError::NoContentLength(key.to_string()));
+            };
+            let size = size as usize;
             let length = size / size_of_t;
-            assert!(size % size_of_t == 0);
+            if !size.is_multiple_of(size_of_t) {
+                return Err(DownloadVecError::UnalignedSize {
+                    key: key.to_string(),
+                    size,
+                    element_size: size_of_t,
+                });
+            }
 
             let mut vec: Vec<T> = vec![T::default(); length];
 
             let mut offset = 0;
-            while let Some(Ok(chunk)) = stream.next().await {
-                unsafe {
-                    let src_ptr = chunk.as_ptr();
-                    let dst_ptr = vec.as_mut_ptr() as *mut u8;
-                    let src_len = chunk.len();
-                    assert!(offset + src_len <= vec.len() * size_of_t);
-                    std::ptr::copy_nonoverlapping(src_ptr, dst_ptr.add(offset), src_len);
-                    offset += src_len;
+            let mut failure_count = 0;
+            let mut throttle = options.throttle();
+            loop {
+                match stream.next().await {
+                    Some(Ok(chunk)) => {
+                        failure_count = 0;
+                        if let Some(throttle) = throttle.as_mut() {
+                            throttle.consume(chunk.len()).await;
+                        }
+                        let src_len = chunk.len();
+                        if offset + src_len > size {
+                            return Err(DownloadVecError::LengthMismatch {
+                                key: key.to_string(),
+                                expected: size,
+                                received: offset + src_len,
+                            });
+                        }
+                        unsafe {
+                            let dst_ptr = vec.as_mut_ptr() as *mut u8;
+                            std::ptr::copy_nonoverlapping(
+                                chunk.as_ptr(),
+                                dst_ptr.add(offset),
+                                src_len,
+                            );
+                        }
+                        offset += src_len;
+                    }
+                    Some(Err(e)) => {
+                        failure_count += 1;
+                        if failure_count >= options.max_read_failures() {
+                            return Err(e.into());
+                        }
+                        eprintln!("read failed: {e}. retrying.. ({failure_count})");
+                        // continue with the rest of the same object
+                        let result = client
+                            .get_object()
+                            .bucket(bucket)
+                            .key(key)
+                            .range(format!("bytes={offset}-"))
+                            .set_if_match(o.e_tag.clone())
+                            .customize()
+                            .config_override(options.config_override())
+                            .send()
+                            .await
+                            .map_err(aws_sdk_s3::Error::from)?;
+                        stream = result.body;
+                    }
+                    None => break,
                 }
             }
-            Ok(Some(vec))
+            if offset != size {
+                return Err(DownloadVecError::LengthMismatch {
+                    key: key.to_string(),
+                    expected: size,
+                    received: offset,
+                });
+            }
+
+            Ok(vec)
         }
         Err(e) => {
             let error: aws_sdk_s3::Error = e.into();
             match error {
-                aws_sdk_s3::Error::NoSuchKey(_) => Ok(None),
-                _ => Err(error),
+                aws_sdk_s3::Error::NoSuchKey(_) => Err(DownloadVecError::NotFound(key.to_string())),
+                _ => Err(error.into()),
             }
         }
     }
@@ -54,40 +188,31 @@ pub async fn download_vec<T: Copy + Default>(
 pub async fn stream_vecs(
     mut bytes: ByteStream,
     chunk_size: usize,
-    mut count: Option<usize>,
-) -> impl Stream<Item = Result<Bytes, ByteStreamError>> {
+    count: Option<usize>,
+) -> impl Stream<Item = Result<Bytes, VecStreamError>> {
     stream! {
-        let mut buf = BytesMut::new();
+        let mut rechunker = Rechunker::new(chunk_size, count);
         loop {
-            while buf.len() >= chunk_size {
-                // there's already enough data to read the next vec
-                let chunk = buf.split_to(chunk_size);
-                yield Ok(chunk.freeze());
-                if let Some(c) = count.as_mut() {
-                    *c -= 1;
-                    if *c == 0 {
-                        break;
-                    }
-                }
+            while let Some(chunk) = rechunker.next_chunk() {
+                yield Ok(chunk);
             }
 
-            if count == Some(0) {
+            if rechunker.is_done() {
                 // no more returning!
                 break;
             }
 
             match bytes.try_next().await {
-                Ok(Some(next)) =>  {
-                    buf.extend(next);
-                    continue;
-                }
+                Ok(Some(next)) => rechunker.push(next),
                 Ok(None) => {
-                    // buf better be empty at this point or this is an unexpected eof
-                    assert!(buf.is_empty(), "stream ended unexpectedly");
+                    // anything left over at this point is an unexpected eof
+                    if let Err(e) = rechunker.finish() {
+                        yield Err(e.into());
+                    }
                     break;
                 }
                 Err(e) => {
-                    yield Err(e);
+                    yield Err(e.into());
                     break;
                 }
             }
@@ -97,36 +222,317 @@ pub async fn stream_vecs(
 
 #[derive(Debug, Error)]
 pub enum VecStreamError {
+    #[error(transparent)]
+    InvalidKey(#[from] NameError),
+    #[error("no such key: {0}")]
+    NotFound(String),
     #[error(transparent)]
     ByteStreamError(#[from] ByteStreamError),
     #[error(transparent)]
     StreamInitFailed(#[from] SdkError<GetObjectError>),
+    #[error(transparent)]
+    HeadFailed(#[from] SdkError<HeadObjectError>),
+    #[error(transparent)]
+    PartialChunk(#[from] PartialChunk),
+}
+
+#[derive(Debug, Error)]
+pub enum HeadError {
+    #[error(transparent)]
+    InvalidKey(#[from] NameError),
+    #[error("no such key: {0}")]
+    NotFound(String),
+    #[error(transparent)]
+    RequestFailed(#[from] SdkError<HeadObjectError>),
+}
+
+pub async fn head(
+    client: &aws_sdk_s3::Client,
+    bucket: &str,
+    key: &str,
+) -> Result<HeadObjectOutput, HeadError> {
+    head_with_options(client, bucket, key, &OpOptions::default()).await
+}
+
+pub async fn head_with_options(
+    client: &aws_sdk_s3::Client,
+    bucket: &str,
+    key: &str,
+    options: &OpOptions,
+) -> Result<HeadObjectOutput, HeadError> {
+    try_head_with_options(client, bucket, key, options)
+        .await?
+        .ok_or_else(|| HeadError::NotFound(key.to_string()))
+}
+
+/// Like `head`, but returns `None` if the object doesn't exist.
+pub async fn try_head(
+    client: &aws_sdk_s3::Client,
+    bucket: &str,
+    key: &str,
+) -> Result<Option<HeadObjectOutput>, HeadError> {
+    try_head_with_options(client, bucket, key, &OpOptions::default()).await
+}
+
+pub async fn try_head_with_options(
+    client: &aws_sdk_s3::Client,
+    bucket: &str,
+    key: &str,
+    options: &OpOptions,
+) -> Result<Option<HeadObjectOutput>, HeadError> {
+    ObjectKey::validate(key)?;
+    Ok(send_head(client, bucket, key, options).await?)
+}
+
+async fn send_head(
+    client: &aws_sdk_s3::Client,
+    bucket: &str,
+    key: &str,
+    options: &OpOptions,
+) -> Result<Option<HeadObjectOutput>, SdkError<HeadObjectError>> {
+    let result = client
+        .head_object()
+        .bucket(bucket)
+        .key(key)
+        .customize()
+        .config_override(options.config_override())
+        .send()
+        .await;
+
+    match result {
+        Ok(output) => Ok(Some(output)),
+        Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => Ok(None),
+        Err(e) => Err(e),
+    }
 }
 
 pub async fn stream_vecs_from(
     client: Arc<aws_sdk_s3::Client>,
     bucket: String,
     key: String,
+    start_index: usize,
+    end_index: Option<usize>,
+    chunk_size: usize,
+) -> impl Stream<Item = Result<Bytes, VecStreamError>> {
+    stream_vecs_from_with_options(
+        client,
+        bucket,
+        key,
+        start_index,
+        end_index,
+        chunk_size,
+        OpOptions::default(),
+    )
+    .await
+}
+
+pub async fn stream_vecs_from_with_options(
+    client: Arc<aws_sdk_s3::Client>,
+    bucket: String,
+    key: String,
+    start_index: usize,
+    end_index: Option<usize>,
+    chunk_size: usize,
+    options: OpOptions,
+) -> impl Stream<Item = Result<Bytes, VecStreamError>> {
+    stream_version_vecs_from(
+        client,
+        bucket,
+        key,
+        None,
+        start_index,
+        end_index,
+        chunk_size,
+        options,
+    )
+    .await
+}
+
+/// Like `stream_vecs_from`, but returns `None` if the object doesn't exist
+/// rather than a stream that fails with `NotFound`.
+pub async fn try_stream_vecs_from(
+    client: Arc<aws_sdk_s3::Client>,
+    bucket: String,
+    key: String,
+    start_index: usize,
+    end_index: Option<usize>,
+    chunk_size: usize,
+) -> Result<Option<impl Stream<Item = Result<Bytes, VecStreamError>>>, VecStreamError> {
+    try_stream_vecs_from_with_options(
+        client,
+        bucket,
+        key,
+        start_index,
+        end_index,
+        chunk_size,
+        OpOptions::default(),
+    )
+    .await
+}
+
+pub async fn try_stream_vecs_from_with_options(
+    client: Arc<aws_sdk_s3::Client>,
+    bucket: String,
+    key: String,
+    start_index: usize,
+    end_index: Option<usize>,
+    chunk_size: usize,
+    options: OpOptions,
+) -> Result<Option<impl Stream<Item = Result<Bytes, VecStreamError>>>, VecStreamError> {
+    ObjectKey::validate(&key)?;
+    let first = if end_index.is_some_and(|end_index| start_index >= end_index) {
+        // an empty range can't be requested, so only check that the object exists
+        if send_head(&client, &bucket, &key, &options).await?.is_none() {
+            return Ok(None);
+        }
+        None
+    } else {
+        // the first range is requested right away, so a missing key is
+        // noticed on the same request that starts reading it
+        let result = get_chunks(
+            &client,
+            &bucket,
+            &key,
+            None,
+            start_index,
+            end_index,
+            chunk_size,
+            &options,
+        )
+        .await;
+        match result {
+            Ok(output) => Some(output),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => {
+                return Ok(None);
+            }
+            Err(e) => return Err(e.into()),
+        }
+    };
+
+    Ok(Some(stream_chunks_from(
+        client,
+        bucket,
+        key,
+        None,
+        first,
+        start_index,
+        end_index,
+        chunk_size,
+        options,
+    )))
+}
+
+/// Request the chunks from `start_index` up to `end_index`, or up to the
+/// end of the object if there is no end index.
+#[allow(clippy::too_many_arguments)]
+async fn get_chunks(
+    client: &aws_sdk_s3::Client,
+    bucket: &str,
+    key: &str,
+    version_id: Option<String>,
+    start_index: usize,
+    end_index: Option<usize>,
+    chunk_size: usize,
+    options: &OpOptions,
+) -> Result<GetObjectOutput, SdkError<GetObjectError>> {
+    let start_pos = start_index * chunk_size;
+    let range = if let Some(end_index) = end_index.as_ref() {
+        let end_pos = end_index * chunk_size - 1;
+        format!("bytes={}-{}", start_pos, end_pos)
+    } else {
+        format!("bytes={}-", start_pos)
+    };
+    client
+        .get_object()
+        .range(range)
+        .bucket(bucket)
+        .key(key)
+        .set_version_id(version_id)
+        .customize()
+        .config_override(options.config_override())
+        .send()
+        .await
+}
+
+/// Like `stream_vecs_from`, but reads a specific version of the object, or
+/// the current one if `version_id` is `None`.
+#[allow(clippy::too_many_arguments)]
+pub async fn stream_version_vecs_from(
+    client: Arc<aws_sdk_s3::Client>,
+    bucket: String,
+    key: String,
+    version_id: Option<String>,
+    start_index: usize,
+    end_index: Option<usize>,
+    chunk_size: usize,
+    options: OpOptions,
+) -> impl Stream<Item = Result<Bytes, VecStreamError>> {
+    stream_chunks_from(
+        client,
+        bucket,
+        key,
+        version_id,
+        None,
+        start_index,
+        end_index,
+        chunk_size,
+        options,
+    )
+}
+
+/// Stream chunks starting at `start_index`, reading `first` before sending
+/// any request of its own if it is given.
+#[allow(clippy::too_many_arguments)]
+fn stream_chunks_from(
+    client: Arc<aws_sdk_s3::Client>,
+    bucket: String,
+    key: String,
+    version_id: Option<String>,
+    mut first: Option<GetObjectOutput>,
     mut start_index: usize,
     end_index: Option<usize>,
     chunk_size: usize,
+    options: OpOptions,
 ) -> impl Stream<Item = Result<Bytes, VecStreamError>> {
     stream! {
+        if let Err(e) = ObjectKey::validate(&key) {
+            yield Err(e.into());
+            return;
+        }
         let mut failure_count = 0;
+        let mut throttle = options.throttle();
         'outer: loop {
-            let start_pos = start_index * chunk_size;
-            let range = if let Some(end_index) = end_index.as_ref() {
-                let end_pos = end_index * chunk_size - 1;
-                format!("bytes={}-{}", start_pos, end_pos)
-            } else {
-                format!("bytes={}-", start_pos)
+            if end_index.is_some_and(|end_index| start_index >= end_index) {
+                // an empty range can't be requested
+                break 'outer;
+            }
+            let result = match first.take() {
+                Some(output) => Ok(output),
+                None => {
+                    get_chunks(
+                        &client,
+                        &bucket,
+                        &key,
+                        version_id.clone(),
+                        start_index,
+                        end_index,
+                        chunk_size,
+                        &options,
+                    )
+                    .await
+                }
+            };
+            let result = match result {
+                Ok(result) => result,
+                Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => {
+                    yield Err(VecStreamError::NotFound(key.clone()));
+                    break 'outer;
+                }
+                Err(e) => {
+                    yield Err(e.into());
+                    break 'outer;
+                }
             };
-            let result = client.get_object()
-                .range(range)
-                .bucket(&bucket)
-                .key(&key)
-                .send()
-                .await?;
 
             let count = end_index.map(|e| e - start_index);
             let mut stream = pin!(stream_vecs(result.body, chunk_size, count).await);
@@ -135,13 +541,21 @@ pub async fn stream_vecs_from(
                     Some(Ok(vec)) =>  {
                         failure_count = 0;
                         start_index += 1;
+                        if let Some(throttle) = throttle.as_mut() {
+                            throttle.consume(vec.len()).await;
+                        }
                         yield Ok(vec);
                     }
+                    Some(Err(e @ VecStreamError::PartialChunk(_))) => {
+                        // the object itself is truncated, so reading it again won't help
+                        yield Err(e);
+                        break 'outer;
+                    }
                     Some(Err(e)) => {
                         failure_count += 1;
-                        if failure_count >= 5 {
-                            // 5 failures with no actual result read. time to just fail for real.
-                            yield Err(e.into());
+                        if failure_count >= options.max_read_failures() {
+                            // too many failures with no actual result read. time to just fail for real.
+                            yield Err(e);
                             break 'outer;
                         } else {
                             // but if not, try again
@@ -166,11 +580,42 @@ pub async fn concurrent_stream_vecs_from(
     start_index: usize,
     end_index: Option<usize>,
     chunk_size: usize,
+) -> impl Stream<Item = Result<Bytes, VecStreamError>> {
+    concurrent_stream_vecs_from_with_options(
+        client,
+        bucket,
+        key,
+        start_index,
+        end_index,
+        chunk_size,
+        OpOptions::default(),
+    )
+    .await
+}
+
+pub async fn concurrent_stream_vecs_from_with_options(
+    client: Arc<aws_sdk_s3::Client>,
+    bucket: String,
+    key: String,
+    start_index: usize,
+    end_index: Option<usize>,
+    chunk_size: usize,
+    options: OpOptions,
 ) -> impl Stream<Item = Result<Bytes, VecStreamError>> {
     let (tx, rx) = tokio::sync::mpsc::channel(10);
     tokio::spawn(async move {
-        let mut stream =
-            pin!(stream_vecs_from(client, bucket, key, start_index, end_index, chunk_size).await);
+        let mut stream = pin!(
+            stream_vecs_from_with_options(
+                client,
+                bucket,
+                key,
+                start_index,
+                end_index,
+                chunk_size,
+                options
+            )
+            .await
+        );
         loop {
             let next = stream.next().await;
             let is_last = !matches!(next.as_ref(), Some(Ok(_)));
@@ -185,3 +630,274 @@ pub async fn concurrent_stream_vecs_from(
         .take_while(|v| futures::future::ready(v.is_some()))
         .map(|v| v.unwrap())
 }
+
+#[derive(Debug, Error)]
+pub enum AsOfError {
+    #[error(transparent)]
+    InvalidKey(#[from] NameError),
+    #[error("versioning has never been enabled on bucket {0}")]
+    VersioningDisabled(String),
+    #[error("no version of {0} existed at the given time")]
+    NoVersion(String),
+    #[error("could not get bucket versioning: {0}")]
+    VersioningCheckFailed(#[from] SdkError<GetBucketVersioningError>),
+    #[error("could not list object versions: {0}")]
+    ListVersionsFailed(#[from] SdkError<ListObjectVersionsError>),
+}
+
+/// Find the id of the version of an object that was current at `timestamp`.
+///
+/// Returns `None` if the object did not exist at that time, either because
+/// it wasn't written yet or because it was deleted.
+pub async fn version_as_of(
+    client: &aws_sdk_s3::Client,
+    bucket: &str,
+    key: &str,
+    timestamp: DateTime,
+) -> Result<Option<String>, AsOfError> {
+    ObjectKey::validate(key)?;
+    let versioning = client.get_bucket_versioning().bucket(bucket).send().await?;
+    if versioning.status.is_none() {
+        return Err(AsOfError::VersioningDisabled(bucket.to_string()));
+    }
+
+    // (last modified, version id), where a version id of None is a delete marker
+    let mut latest: Option<(DateTime, Option<String>)> = None;
+    let mut key_marker = None;
+    let mut version_id_marker = None;
+    loop {
+        let listing = client
+            .list_object_versions()
+            .bucket(bucket)
+            .prefix(key)
+            .set_key_marker(key_marker.take())
+            .set_version_id_marker(version_id_marker.take())
+            .send()
+            .await?;
+
+        let versions = listing
+            .versions()
+            .iter()
+            .map(|v| (v.key(), v.last_modified(), v.version_id().map(String::from)));
+        let delete_markers = listing
+            .delete_markers()
+            .iter()
+            .map(|m| (m.key(), m.last_modified(), None));
+        for (version_key, last_modified, version_id) in versions.chain(delete_markers) {
+            let Some(last_modified) = last_modified else {
+                continue;
+            };
+            if version_key != Some(key) || *last_modified > timestamp {
+                continue;
+            }
+            if latest
+                .as_ref()
+                .is_none_or(|(latest_modified, _)| last_modified > latest_modified)
+            {
+                latest = Some((*last_modified, version_id));
+            }
+        }
+
+        // versions are listed by key, so once we're past our key there's nothing left
+        let past_key = listing.next_key_marker().is_some_and(|k| k > key);
+        if listing.is_truncated() != Some(true) || past_key {
+            break;
+        }
+        key_marker = listing.next_key_marker;
+        version_id_marker = listing.next_version_id_marker;
+    }
+
+    Ok(latest.and_then(|(_, version_id)| version_id))
+}
+
+/// Stream an object as it was at `timestamp`, using the bucket's version
+/// history.
+pub async fn stream_vecs_as_of(
+    client: Arc<aws_sdk_s3::Client>,
+    bucket: String,
+    key: String,
+    timestamp: DateTime,
+    chunk_size: usize,
+) -> Result<impl Stream<Item = Result<Bytes, VecStreamError>>, AsOfError> {
+    stream_vecs_as_of_with_options(
+        client,
+        bucket,
+        key,
+        timestamp,
+        chunk_size,
+        OpOptions::default(),
+    )
+    .await
+}
+
+pub async fn stream_vecs_as_of_with_options(
+    client: Arc<aws_sdk_s3::Client>,
+    bucket: String,
+    key: String,
+    timestamp: DateTime,
+    chunk_size: usize,
+    options: OpOptions,
+) -> Result<impl Stream<Item = Result<Bytes, VecStreamError>>, AsOfError> {
+    let Some(version_id) = version_as_of(&client, &bucket, &key, timestamp).await? else {
+        return Err(AsOfError::NoVersion(key));
+    };
+
+    Ok(stream_version_vecs_from(
+        client,
+        bucket,
+        key,
+        Some(version_id),
+        0,
+        None,
+        chunk_size,
+        options,
+    )
+    .await)
+}
+
+/// A pool buffer filled with whole chunks.
+pub struct FilledBuffer<B> {
+    pub buffer: B,
+    /// The amount of bytes filled, which is less than the length of the
+    /// buffer only for the last one.
+    pub len: usize,
+    /// The index of the first chunk in the buffer.
+    pub start_index: usize,
+}
+
+/// Like `stream_vecs_from`, but copies the object straight into buffers
+/// from `pool` rather than allocating chunks.
+///
+/// Each buffer must be a multiple of `chunk_size` long. Buffers are yielded
+/// once full, and it is up to the consumer to release them back to the
+/// pool.
+///
+/// If the object ends in a partial chunk, the whole chunks before it are
+/// still yielded, followed by an error.
+#[allow(clippy::too_many_arguments)]
+pub async fn stream_into_buffers<P>(
+    client: Arc<aws_sdk_s3::Client>,
+    bucket: String,
+    key: String,
+    pool: Arc<P>,
+    start_index: usize,
+    end_index: Option<usize>,
+    chunk_size: usize,
+    options: OpOptions,
+) -> impl Stream<Item = Result<FilledBuffer<P::Buffer>, VecStreamError>>
+where
+    P: BufferPool + 'static,
+{
+    stream! {
+        if let Err(e) = ObjectKey::validate(&key) {
+            yield Err(e.into());
+            return;
+        }
+        let mut offset = start_index * chunk_size;
+        let end_pos = end_index.map(|e| e * chunk_size);
+        let mut failure_count = 0;
+        let mut throttle = options.throttle();
+        // the buffer being filled and its byte offset in the object. it goes
+        // back to the pool if the stream fails or is dropped before it's full
+        let mut current: Option<(Pooled<P>, usize)> = None;
+        'outer: loop {
+            if end_pos.is_some_and(|end_pos| offset >= end_pos) {
+                break;
+            }
+            let range = if let Some(end_pos) = end_pos {
+                format!("bytes={}-{}", offset, end_pos - 1)
+            } else {
+                format!("bytes={}-", offset)
+            };
+            let result = client.get_object()
+                .range(range)
+                .bucket(&bucket)
+                .key(&key)
+                .customize()
+                .config_override(options.config_override())
+                .send()
+                .await;
+            let result = match result {
+                Ok(result) => result,
+                Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => {
+                    yield Err(VecStreamError::NotFound(key.clone()));
+                    return;
+                }
+                Err(e) => {
+                    yield Err(e.into());
+                    return;
+                }
+            };
+
+            let mut body = result.body;
+            loop {
+                match body.next().await {
+                    Some(Ok(mut data)) => {
+                        failure_count = 0;
+                        if let Some(throttle) = throttle.as_mut() {
+                            throttle.consume(data.len()).await;
+                        }
+                        while !data.is_empty() {
+                            if current.is_none() {
+                                let mut buffer = Pooled::new(pool.clone(), pool.acquire().await, 0);
+                                let len = buffer.buffer_mut().len();
+                                assert!(
+                                    len != 0 && len.is_multiple_of(chunk_size),
+                                    "pool buffers must be a multiple of the chunk size"
+                                );
+                                current = Some((buffer, offset));
+                            }
+                            let (buffer, _) = current.as_mut().unwrap();
+                            let filled = buffer.len();
+                            let target = &mut buffer.buffer_mut()[filled..];
+                            let n = target.len().min(data.len());
+                            target[..n].copy_from_slice(&data.split_to(n));
+                            buffer.set_len(filled + n);
+                            offset += n;
+
+                            if buffer.len() == buffer.buffer_mut().len() {
+                                let (buffer, buffer_offset) = current.take().unwrap();
+                                let (buffer, len) = buffer.into_inner();
+                                yield Ok(FilledBuffer {
+                                    buffer,
+                                    len,
+                                    start_index: buffer_offset / chunk_size,
+                                });
+                            }
+                        }
+                    }
+                    Some(Err(e)) => {
+                        failure_count += 1;
+                        if failure_count >= options.max_read_failures() {
+                            yield Err(e.into());
+                            return;
+                        }
+                        // the partially filled buffer is kept, so we continue at the exact byte
+                        eprintln!("read failed: {e}. retrying.. ({failure_count})");
+                        continue 'outer;
+                    }
+                    None => break 'outer,
+                }
+            }
+        }
+
+        if let Some((mut buffer, buffer_offset)) = current.take() {
+            // the whole chunks are still good, anything after them is not
+            let partial = buffer.len() % chunk_size;
+            buffer.set_len(buffer.len() - partial);
+            if buffer.is_empty() {
+                drop(buffer);
+            } else {
+                let (buffer, len) = buffer.into_inner();
+                yield Ok(FilledBuffer {
+                    buffer,
+                    len,
+                    start_index: buffer_offset / chunk_size,
+                });
+            }
+            if partial != 0 {
+                yield Err(PartialChunk { len: partial, chunk_size }.into());
+            }
+        }
+    }
+}
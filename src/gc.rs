@@ -0,0 +1,172 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, SystemTime},
+};
+
+use aws_sdk_s3::{
+    error::SdkError,
+    operation::list_objects_v2::ListObjectsV2Error,
+    primitives::DateTime,
+    types::{Delete, ObjectIdentifier},
+    Client,
+};
+use thiserror::Error;
+
+use crate::manifest::Manifest;
+
+/// S3 deletes at most this many objects per request.
+const DELETE_BATCH_SIZE: usize = 1000;
+
+#[derive(Clone, Debug)]
+pub struct GcOptions {
+    /// Only report what would be deleted.
+    pub dry_run: bool,
+    /// Objects modified more recently than this are never deleted, as they
+    /// may belong to a build whose manifest isn't written yet.
+    pub min_age: Duration,
+    /// Additional keys to keep, such as the manifests themselves.
+    pub keep: HashSet<String>,
+}
+
+impl Default for GcOptions {
+    fn default() -> Self {
+        Self {
+            dry_run: true,
+            min_age: Duration::from_secs(24 * 60 * 60),
+            keep: HashSet::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct GcReport {
+    pub dry_run: bool,
+    /// Keys under the prefix that are still referenced.
+    pub referenced: usize,
+    /// Unreferenced keys that were deleted, or would be in a dry run.
+    pub deleted: Vec<String>,
+    /// Unreferenced keys that are too recent to delete.
+    pub too_recent: Vec<String>,
+    /// Keys that could not be deleted, along with the reason.
+    pub failed: Vec<(String, String)>,
+    /// The size in bytes of every unreferenced key old enough to delete.
+    pub sizes: HashMap<String, usize>,
+}
+
+#[derive(Debug, Error)]
+pub enum GcError {
+    #[error("could not list objects: {0}")]
+    ListFailed(#[from] SdkError<ListObjectsV2Error>),
+}
+
+/// Delete all objects under `prefix` that aren't referenced by any of the
+/// given manifests.
+pub async fn gc_prefix(
+    client: &Client,
+    bucket: &str,
+    prefix: &str,
+    manifests: &[Manifest],
+    options: GcOptions,
+) -> Result<GcReport, GcError> {
+    let referenced: HashSet<String> = manifests
+        .iter()
+        .flat_map(|m| m.keys())
+        .map(String::from)
+        .collect();
+
+    gc_unreferenced(client, bucket, prefix, &referenced, options).await
+}
+
+/// Delete all objects under `prefix` whose key isn't in `referenced`.
+pub async fn gc_unreferenced(
+    client: &Client,
+    bucket: &str,
+    prefix: &str,
+    referenced: &HashSet<String>,
+    options: GcOptions,
+) -> Result<GcReport, GcError> {
+    let cutoff = DateTime::from(SystemTime::now() - options.min_age);
+    let mut report = GcReport {
+        dry_run: options.dry_run,
+        ..Default::default()
+    };
+
+    let mut unreferenced = Vec::new();
+    let mut pages = client
+        .list_objects_v2()
+        .bucket(bucket)
+        .prefix(prefix)
+        .into_paginator()
+        .send();
+    while let Some(page) = pages.next().await {
+        for object in page?.contents() {
+            let Some(key) = object.key() else {
+                continue;
+            };
+            if referenced.contains(key) || options.keep.contains(key) {
+                report.referenced += 1;
+            } else if object.last_modified().is_none_or(|m| *m > cutoff) {
+                report.too_recent.push(key.to_string());
+            } else {
+                let size = object.size().unwrap_or(0) as usize;
+                report.sizes.insert(key.to_string(), size);
+                unreferenced.push(key.to_string());
+            }
+        }
+    }
+
+    if options.dry_run {
+        report.deleted = unreferenced;
+        return Ok(report);
+    }
+
+    for batch in unreferenced.chunks(DELETE_BATCH_SIZE) {
+        let objects = batch
+            .iter()
+            .map(|key| {
+                ObjectIdentifier::builder()
+                    .key(key)
+                    .build()
+                    .expect("key was set")
+            })
+            .collect();
+        let delete = Delete::builder()
+            .set_objects(Some(objects))
+            .quiet(true)
+            .build()
+            .expect("objects were set");
+        // keep going, so that what was deleted so far still gets reported
+        let result = match client
+            .delete_objects()
+            .bucket(bucket)
+            .delete(delete)
+            .send()
+            .await
+        {
+            Ok(result) => result,
+            Err(e) => {
+                let message = e.to_string();
+                report
+                    .failed
+                    .extend(batch.iter().map(|key| (key.clone(), message.clone())));
+                continue;
+            }
+        };
+
+        let failed: HashSet<&str> = result.errors().iter().filter_map(|e| e.key()).collect();
+        for error in result.errors() {
+            if let Some(key) = error.key() {
+                let message = error.message().unwrap_or("unknown error").to_string();
+                report.failed.push((key.to_string(), message));
+            }
+        }
+        report.deleted.extend(
+            batch
+                .iter()
+                .filter(|key| !failed.contains(key.as_str()))
+                .cloned(),
+        );
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,239 @@
+use std::sync::Arc;
+
+use aws_sdk_s3::{
+    error::SdkError,
+    operation::{delete_object::DeleteObjectError, put_object::PutObjectError},
+    Client,
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use thiserror::Error;
+
+use crate::{
+    audit::{AuditOperation, Auditor},
+    download,
+    object::{self, ObjectError},
+    quota::{Quota, QuotaExceeded},
+};
+
+/// Maps keys as given by the caller to the keys actually stored in the
+/// bucket.
+pub trait KeyMapper: Send + Sync {
+    fn map_key(&self, key: &str) -> String;
+}
+
+/// Replaces every path segment of a key with a truncated HMAC-SHA256 of
+/// that segment.
+///
+/// Mapping per segment keeps the `/` structure intact, so that a mapped
+/// prefix is still a prefix of all mapped keys under it. Use
+/// [`KeyMapper::map_key`] on a prefix to find what it is stored as.
+pub struct HmacKeyMapper {
+    secret: Vec<u8>,
+}
+
+impl HmacKeyMapper {
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            secret: secret.into(),
+        }
+    }
+
+    fn map_segment(&self, segment: &str) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.secret).expect("hmac accepts keys of any size");
+        mac.update(segment.as_bytes());
+        let digest = mac.finalize().into_bytes();
+
+        digest[..16].iter().map(|b| format!("{b:02x}")).collect()
+    }
+}
+
+impl KeyMapper for HmacKeyMapper {
+    fn map_key(&self, key: &str) -> String {
+        key.split('/')
+            .map(|segment| {
+                if segment.is_empty() {
+                    String::new()
+                } else {
+                    self.map_segment(segment)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum KeyLookupError {
+    #[error("no key sidecar configured")]
+    NoSidecar,
+    #[error("could not fetch key sidecar: {0}")]
+    FetchFailed(#[from] ObjectError),
+    #[error("key sidecar is not valid utf-8")]
+    InvalidKey(#[from] std::string::FromUtf8Error),
+}
+
+#[derive(Debug, Error)]
+pub enum KeyRecordError {
+    #[error(transparent)]
+    QuotaExceeded(#[from] QuotaExceeded),
+    #[error("could not write key sidecar: {0}")]
+    PutFailed(#[from] SdkError<PutObjectError>),
+}
+
+#[derive(Debug, Error)]
+pub enum KeyForgetError {
+    #[error("could not delete key sidecar: {0}")]
+    DeleteFailed(#[from] SdkError<DeleteObjectError>),
+}
+
+/// A key mapper, optionally recording every mapping it hands out in a
+/// sidecar.
+///
+/// The sidecar stores the original key as the content of an object named
+/// after the mapped key, under a separate prefix which is not mapped
+/// itself. That way names stay opaque, while an operator with read access
+/// to the sidecar can still find out what an object is. Anyone who can
+/// read the sidecar can reverse the mapping, so keep it in a separate
+/// bucket with `with_sidecar_bucket` where that matters.
+#[derive(Clone)]
+pub struct KeyMapping {
+    mapper: Arc<dyn KeyMapper>,
+    sidecar_prefix: Option<String>,
+    sidecar_bucket: Option<String>,
+}
+
+impl KeyMapping {
+    pub fn new(mapper: Arc<dyn KeyMapper>) -> Self {
+        Self {
+            mapper,
+            sidecar_prefix: None,
+            sidecar_bucket: None,
+        }
+    }
+
+    pub fn with_sidecar(mut self, prefix: impl Into<String>) -> Self {
+        self.sidecar_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Keep the sidecar in `bucket` rather than next to the mapped objects.
+    pub fn with_sidecar_bucket(mut self, bucket: impl Into<String>) -> Self {
+        self.sidecar_bucket = Some(bucket.into());
+        self
+    }
+
+    pub fn map_key(&self, key: &str) -> String {
+        self.mapper.map_key(key)
+    }
+
+    /// The bucket and key of the sidecar entry for `mapped`, where `bucket`
+    /// is the one the mapped object lives in.
+    fn sidecar_location<'a>(&'a self, bucket: &'a str, mapped: &str) -> Option<(&'a str, String)> {
+        let prefix = self.sidecar_prefix.as_ref()?;
+        let bucket = self.sidecar_bucket.as_deref().unwrap_or(bucket);
+        Some((bucket, format!("{prefix}{mapped}")))
+    }
+
+    /// Record that `key` is stored as `mapped`, if a sidecar is configured.
+    ///
+    /// This should happen once the object itself has been written. The
+    /// sidecar entry is charged against `quota` and audited like any other
+    /// write.
+    pub async fn record(
+        &self,
+        client: &Client,
+        bucket: &str,
+        key: &str,
+        mapped: &str,
+        auditor: Option<&Auditor>,
+        quota: Option<&Quota>,
+    ) -> Result<(), KeyRecordError> {
+        let Some((bucket, sidecar_key)) = self.sidecar_location(bucket, mapped) else {
+            return Ok(());
+        };
+        let size = key.len();
+        if let Some(quota) = quota {
+            quota.charge(&sidecar_key, size)?;
+        }
+        let result = client
+            .put_object()
+            .bucket(bucket)
+            .key(&sidecar_key)
+            .body(key.as_bytes().to_vec().into())
+            .send()
+            .await;
+        if let (Some(quota), Err(_)) = (quota, &result) {
+            quota.release(&sidecar_key, size);
+        }
+        if let Some(auditor) = auditor {
+            let operation = AuditOperation::Put {
+                key: sidecar_key,
+                size,
+            };
+            auditor.record(bucket, operation, &result).await;
+        }
+        result?;
+
+        Ok(())
+    }
+
+    /// Remove the sidecar entry for `mapped`, if a sidecar is configured.
+    ///
+    /// This should happen once the object itself has been deleted. The
+    /// entry's bytes are given back to `quota` and the delete is audited.
+    pub async fn forget(
+        &self,
+        client: &Client,
+        bucket: &str,
+        mapped: &str,
+        auditor: Option<&Auditor>,
+        quota: Option<&Quota>,
+    ) -> Result<(), KeyForgetError> {
+        let Some((bucket, sidecar_key)) = self.sidecar_location(bucket, mapped) else {
+            return Ok(());
+        };
+        let size = match quota {
+            Some(_) => download::try_head(client, bucket, &sidecar_key)
+                .await
+                .ok()
+                .flatten()
+                .and_then(|head| head.content_length)
+                .map(|len| len as usize),
+            None => None,
+        };
+        let result = client
+            .delete_object()
+            .bucket(bucket)
+            .key(&sidecar_key)
+            .send()
+            .await;
+        if let (Some(quota), Some(size), Ok(_)) = (quota, size, &result) {
+            quota.release(&sidecar_key, size);
+        }
+        if let Some(auditor) = auditor {
+            let operation = AuditOperation::Delete { key: sidecar_key };
+            auditor.record(bucket, operation, &result).await;
+        }
+        result?;
+
+        Ok(())
+    }
+
+    /// Find the original key for a mapped key in the sidecar.
+    pub async fn lookup(
+        &self,
+        client: &Client,
+        bucket: &str,
+        mapped: &str,
+    ) -> Result<Option<String>, KeyLookupError> {
+        let Some((bucket, sidecar_key)) = self.sidecar_location(bucket, mapped) else {
+            return Err(KeyLookupError::NoSidecar);
+        };
+        match object::get_bytes(client, bucket, &sidecar_key).await? {
+            Some(data) => Ok(Some(String::from_utf8(data.to_vec())?)),
+            None => Ok(None),
+        }
+    }
+}
@@ -1,3 +1,27 @@
+// the aws sdk error types are large, and we pass them around as-is
+#![allow(clippy::result_large_err, clippy::large_enum_variant)]
+
+pub mod audit;
+pub mod broadcast;
+pub mod chunk;
 pub mod client;
+pub mod compress;
 pub mod download;
+pub mod gc;
+pub mod keymap;
+pub mod manifest;
+pub mod merge;
+pub mod migrate;
+pub mod names;
+pub mod object;
+pub mod options;
+pub mod pipeline;
+pub mod pool;
+pub mod presign;
+pub mod quota;
+#[cfg(feature = "replay")]
+pub mod replay;
+pub mod scoped;
+pub mod session;
+pub mod store;
 pub mod upload;
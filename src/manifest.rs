@@ -0,0 +1,81 @@
+use aws_sdk_s3::Client;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::object::{self, ObjectError};
+
+/// One shard of a sharded dataset.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ShardEntry {
+    pub key: String,
+    /// The global index of the first chunk in this shard.
+    pub start_index: usize,
+    /// The amount of chunks in this shard.
+    pub count: usize,
+    /// The distance between the global indexes of consecutive chunks. This
+    /// is 1 for shards holding a contiguous range, and the amount of shards
+    /// for datasets that are sharded round-robin.
+    #[serde(default = "default_stride")]
+    pub stride: usize,
+}
+
+fn default_stride() -> usize {
+    1
+}
+
+impl ShardEntry {
+    /// The global index of the chunk at `index` within this shard.
+    pub fn global_index(&self, index: usize) -> usize {
+        self.start_index + index * self.stride
+    }
+
+    /// One past the global index of the last chunk in this shard.
+    pub fn end_index(&self) -> usize {
+        if self.count == 0 {
+            self.start_index
+        } else {
+            self.global_index(self.count - 1) + 1
+        }
+    }
+}
+
+/// Describes a dataset made up of shards of fixed size chunks.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub chunk_size: usize,
+    pub shards: Vec<ShardEntry>,
+}
+
+#[derive(Debug, Error)]
+pub enum ManifestError {
+    #[error("manifest {0} does not exist")]
+    NotFound(String),
+    #[error(transparent)]
+    Object(#[from] ObjectError),
+}
+
+impl Manifest {
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.shards.iter().map(|s| s.key.as_str())
+    }
+
+    /// The total amount of chunks over all shards.
+    pub fn count(&self) -> usize {
+        self.shards.iter().map(|s| s.count).sum()
+    }
+
+    pub async fn load(client: &Client, bucket: &str, key: &str) -> Result<Self, ManifestError> {
+        object::load_json(client, bucket, key)
+            .await?
+            .ok_or_else(|| ManifestError::NotFound(key.to_string()))
+    }
+
+    pub async fn save(
+        &self,
+        client: &Client,
+        bucket: &str,
+        key: &str,
+    ) -> Result<(), ManifestError> {
+        Ok(object::save_json(client, bucket, key, self).await?)
+    }
+}
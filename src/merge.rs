@@ -0,0 +1,116 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::pin::pin;
+use std::sync::Arc;
+
+use async_stream::stream;
+use bytes::Bytes;
+use futures::stream::StreamExt;
+use futures::Stream;
+use tokio::sync::mpsc;
+
+use crate::download::{stream_vecs_from, VecStreamError};
+use crate::manifest::Manifest;
+
+/// Tag every chunk of a shard with its global index.
+pub fn tag_indices<S, E>(
+    stream: S,
+    start_index: usize,
+    stride: usize,
+) -> impl Stream<Item = Result<(usize, Bytes), E>>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+{
+    stream
+        .enumerate()
+        .map(move |(ix, chunk)| chunk.map(|chunk| (start_index + ix * stride, chunk)))
+}
+
+/// Merge several streams of index-tagged chunks into one stream in global
+/// index order.
+///
+/// Every input stream must itself be in index order. Each one is driven in
+/// its own task, reading up to `prefetch` chunks ahead. The merged stream
+/// ends at the first error.
+pub fn merge_by_index<S, E>(
+    streams: Vec<S>,
+    prefetch: usize,
+) -> impl Stream<Item = Result<(usize, Bytes), E>>
+where
+    S: Stream<Item = Result<(usize, Bytes), E>> + Send + 'static,
+    E: Send + 'static,
+{
+    let mut receivers = Vec::with_capacity(streams.len());
+    for stream in streams {
+        let (tx, rx) = mpsc::channel(prefetch.max(1));
+        tokio::spawn(async move {
+            let mut stream = pin!(stream);
+            while let Some(next) = stream.next().await {
+                let is_last = next.is_err();
+                if tx.send(next).await.is_err() || is_last {
+                    break;
+                }
+            }
+        });
+        receivers.push(rx);
+    }
+
+    stream! {
+        let mut heads: Vec<Option<Bytes>> = vec![None; receivers.len()];
+        let mut heap = BinaryHeap::with_capacity(receivers.len());
+        for (shard, rx) in receivers.iter_mut().enumerate() {
+            match rx.recv().await {
+                Some(Ok((index, chunk))) => {
+                    heads[shard] = Some(chunk);
+                    heap.push(Reverse((index, shard)));
+                }
+                Some(Err(e)) => {
+                    yield Err(e);
+                    return;
+                }
+                None => {}
+            }
+        }
+
+        while let Some(Reverse((index, shard))) = heap.pop() {
+            let chunk = heads[shard].take().expect("every shard in the heap has a head");
+            yield Ok((index, chunk));
+
+            match receivers[shard].recv().await {
+                Some(Ok((index, chunk))) => {
+                    heads[shard] = Some(chunk);
+                    heap.push(Reverse((index, shard)));
+                }
+                Some(Err(e)) => {
+                    yield Err(e);
+                    return;
+                }
+                None => {}
+            }
+        }
+    }
+}
+
+/// Stream all shards of a manifest as one sequence in global index order.
+pub async fn merge_manifest(
+    client: Arc<aws_sdk_s3::Client>,
+    bucket: String,
+    manifest: &Manifest,
+    prefetch: usize,
+) -> impl Stream<Item = Result<(usize, Bytes), VecStreamError>> {
+    let mut streams = Vec::with_capacity(manifest.shards.len());
+    for shard in manifest.shards.iter().filter(|s| s.count != 0) {
+        let stream = stream_vecs_from(
+            client.clone(),
+            bucket.clone(),
+            shard.key.clone(),
+            0,
+            Some(shard.count),
+            manifest.chunk_size,
+        )
+        .await;
+        streams.push(tag_indices(stream, shard.start_index, shard.stride));
+    }
+
+    merge_by_index(streams, prefetch)
+}
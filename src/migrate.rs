@@ -0,0 +1,179 @@
+use std::time::SystemTime;
+
+use aws_sdk_s3::{
+    error::SdkError, operation::list_objects_v2::ListObjectsV2Error, primitives::ByteStream, Client,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use thiserror::Error;
+
+use crate::upload::{PartId, UnsupportedVersion, UploadInfoV1};
+
+#[derive(Debug, Error)]
+pub enum MigrateError {
+    #[error("invalid upload info: {0}")]
+    Invalid(#[from] serde_json::Error),
+    #[error(transparent)]
+    UnsupportedVersion(#[from] UnsupportedVersion),
+}
+
+/// `UploadInfo` as written before it had a version. Parts were plain etag
+/// strings, which `PartId` still accepts, and `created_at` didn't exist yet.
+#[derive(Deserialize)]
+pub(crate) struct LegacyUploadInfo {
+    bucket: String,
+    key: String,
+    size_per_upload: usize,
+    upload_id: String,
+    parts: Vec<PartId>,
+    uploaded_bytes: usize,
+    #[serde(default)]
+    created_at: Option<SystemTime>,
+}
+
+impl From<LegacyUploadInfo> for UploadInfoV1 {
+    fn from(legacy: LegacyUploadInfo) -> Self {
+        Self {
+            version: 1,
+            bucket: legacy.bucket,
+            key: legacy.key,
+            size_per_upload: legacy.size_per_upload,
+            upload_id: legacy.upload_id,
+            parts: legacy.parts,
+            uploaded_bytes: legacy.uploaded_bytes,
+            created_at: legacy.created_at,
+            completed: None,
+        }
+    }
+}
+
+fn migrate_value(value: Value) -> Result<UploadInfoV1, MigrateError> {
+    if value.get("version").is_some() {
+        let info: UploadInfoV1 = serde_json::from_value(value)?;
+        if info.version != 1 {
+            return Err(UnsupportedVersion(info.version).into());
+        }
+        return Ok(info);
+    }
+
+    let legacy: LegacyUploadInfo = serde_json::from_value(value)?;
+    Ok(legacy.into())
+}
+
+/// Upgrade a serialized `UploadInfo` written by any earlier version of this
+/// crate to the current format.
+///
+/// Info that is already current is returned as is.
+pub fn migrate_upload_info(legacy_json: &str) -> Result<UploadInfoV1, MigrateError> {
+    migrate_value(serde_json::from_str(legacy_json)?)
+}
+
+/// Upgrade a stored blob holding either an `UploadInfo` or a
+/// `MultiUploadInfo`, returning the new JSON if anything changed.
+pub fn migrate_blob(json: &str) -> Result<Option<String>, MigrateError> {
+    let value: Value = serde_json::from_str(json)?;
+    let migrated = match value.get("uploads") {
+        Some(Value::Array(uploads)) => {
+            let uploads = uploads
+                .iter()
+                .cloned()
+                .map(migrate_value)
+                .collect::<Result<Vec<_>, _>>()?;
+            json!({ "uploads": uploads })
+        }
+        _ => serde_json::to_value(migrate_value(value.clone())?)?,
+    };
+
+    Ok((migrated != value).then(|| migrated.to_string()))
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct MigrateReport {
+    pub dry_run: bool,
+    /// Keys that were upgraded, or would be in a dry run.
+    pub migrated: Vec<String>,
+    /// Keys that were already in the current format.
+    pub current: Vec<String>,
+    /// Keys that could not be upgraded, along with the reason.
+    pub failed: Vec<(String, String)>,
+}
+
+#[derive(Debug, Error)]
+pub enum MigratePrefixError {
+    #[error("could not list objects: {0}")]
+    ListFailed(#[from] SdkError<ListObjectsV2Error>),
+}
+
+/// Upgrade every upload info blob under `prefix` in place.
+///
+/// Objects that fail to download, parse or upload are reported rather than
+/// stopping the scan, so upgrading again after fixing them is safe.
+pub async fn migrate_prefix(
+    client: &Client,
+    bucket: &str,
+    prefix: &str,
+    dry_run: bool,
+) -> Result<MigrateReport, MigratePrefixError> {
+    let mut report = MigrateReport {
+        dry_run,
+        ..Default::default()
+    };
+
+    let mut pages = client
+        .list_objects_v2()
+        .bucket(bucket)
+        .prefix(prefix)
+        .into_paginator()
+        .send();
+    while let Some(page) = pages.next().await {
+        for object in page?.contents() {
+            let Some(key) = object.key() else {
+                continue;
+            };
+            match migrate_object(client, bucket, key, dry_run).await {
+                Ok(true) => report.migrated.push(key.to_string()),
+                Ok(false) => report.current.push(key.to_string()),
+                Err(e) => report.failed.push((key.to_string(), e)),
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+async fn migrate_object(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    dry_run: bool,
+) -> Result<bool, String> {
+    let o = client
+        .get_object()
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    let e_tag = o.e_tag.clone();
+    let data = o.body.collect().await.map_err(|e| e.to_string())?;
+    let json = String::from_utf8(data.to_vec()).map_err(|e| e.to_string())?;
+    let Some(migrated) = migrate_blob(&json).map_err(|e| e.to_string())? else {
+        return Ok(false);
+    };
+    if dry_run {
+        return Ok(true);
+    }
+
+    // don't overwrite info that a worker updated while we were migrating it
+    client
+        .put_object()
+        .bucket(bucket)
+        .key(key)
+        .set_if_match(e_tag)
+        .body(ByteStream::from(migrated.into_bytes()))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(true)
+}
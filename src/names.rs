@@ -0,0 +1,176 @@
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum NameError {
+    #[error("bucket name {0:?} must be between 3 and 63 characters long")]
+    BucketLength(String),
+    #[error("bucket name {0:?} may only contain lowercase letters, digits, '.' and '-'")]
+    BucketCharacters(String),
+    #[error("bucket name {0:?} must start and end with a letter or digit")]
+    BucketEdges(String),
+    #[error("bucket name {0:?} contains adjacent periods")]
+    BucketAdjacentPeriods(String),
+    #[error("bucket name {0:?} is formatted as an ip address")]
+    BucketIpAddress(String),
+    #[error("key is empty")]
+    EmptyKey,
+    #[error("key {0:?} is longer than 1024 bytes")]
+    KeyLength(String),
+    #[error("key {0:?} contains control characters")]
+    KeyControlCharacters(String),
+}
+
+/// A valid S3 bucket name.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct BucketName(String);
+
+impl BucketName {
+    pub fn new(name: impl Into<String>) -> Result<Self, NameError> {
+        let name = name.into();
+        if !(3..=63).contains(&name.len()) {
+            return Err(NameError::BucketLength(name));
+        }
+        if !name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '.' || c == '-')
+        {
+            return Err(NameError::BucketCharacters(name));
+        }
+        let is_edge = |c: Option<char>| c.is_some_and(|c| c.is_ascii_alphanumeric());
+        if !is_edge(name.chars().next()) || !is_edge(name.chars().last()) {
+            return Err(NameError::BucketEdges(name));
+        }
+        if name.contains("..") {
+            return Err(NameError::BucketAdjacentPeriods(name));
+        }
+        if name.parse::<std::net::Ipv4Addr>().is_ok() {
+            return Err(NameError::BucketIpAddress(name));
+        }
+
+        Ok(Self(name))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// A valid S3 object key: 1 to 1024 bytes of UTF-8 without control
+/// characters.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ObjectKey(String);
+
+impl ObjectKey {
+    pub fn new(key: impl Into<String>) -> Result<Self, NameError> {
+        let key = key.into();
+        Self::validate(&key)?;
+
+        Ok(Self(key))
+    }
+
+    /// Check that `key` is valid without taking ownership of it.
+    pub fn validate(key: &str) -> Result<(), NameError> {
+        if key.is_empty() {
+            return Err(NameError::EmptyKey);
+        }
+        if key.len() > 1024 {
+            return Err(NameError::KeyLength(key.to_string()));
+        }
+        if key.chars().any(char::is_control) {
+            return Err(NameError::KeyControlCharacters(key.to_string()));
+        }
+
+        Ok(())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+macro_rules! string_newtype {
+    ($name:ident) => {
+        impl TryFrom<String> for $name {
+            type Error = NameError;
+
+            fn try_from(value: String) -> Result<Self, NameError> {
+                Self::new(value)
+            }
+        }
+
+        impl TryFrom<&str> for $name {
+            type Error = NameError;
+
+            fn try_from(value: &str) -> Result<Self, NameError> {
+                Self::new(value)
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = NameError;
+
+            fn from_str(s: &str) -> Result<Self, NameError> {
+                Self::new(s)
+            }
+        }
+
+        impl From<$name> for String {
+            fn from(value: $name) -> String {
+                value.0
+            }
+        }
+
+        impl From<&$name> for String {
+            fn from(value: &$name) -> String {
+                value.0.clone()
+            }
+        }
+
+        impl Deref for $name {
+            type Target = str;
+
+            fn deref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl AsRef<str> for $name {
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+    };
+}
+
+string_newtype!(BucketName);
+string_newtype!(ObjectKey);
+
+/// The `x-amz-copy-source` value for copying `key` out of `bucket`.
+///
+/// The key is percent-encoded, except for its `/` separators.
+pub(crate) fn copy_source(bucket: &str, key: &str) -> String {
+    let mut source = format!("{bucket}/");
+    for byte in key.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                source.push(byte as char)
+            }
+            _ => source.push_str(&format!("%{byte:02X}")),
+        }
+    }
+
+    source
+}
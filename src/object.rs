@@ -0,0 +1,66 @@
+use aws_sdk_s3::{
+    error::SdkError,
+    operation::{get_object::GetObjectError, put_object::PutObjectError},
+    primitives::ByteStreamError,
+    Client,
+};
+use bytes::Bytes;
+use serde::{de::DeserializeOwned, Serialize};
+use thiserror::Error;
+
+/// Failure to load or store a small object kept whole in memory, like a
+/// manifest or the quota state.
+#[derive(Debug, Error)]
+pub enum ObjectError {
+    #[error("could not fetch object: {0}")]
+    FetchFailed(#[from] SdkError<GetObjectError>),
+    #[error("could not read object: {0}")]
+    ReadFailed(#[from] ByteStreamError),
+    #[error("could not store object: {0}")]
+    StoreFailed(#[from] SdkError<PutObjectError>),
+    #[error("invalid object: {0}")]
+    Invalid(#[from] serde_json::Error),
+}
+
+/// Fetch the contents of an object, or `None` if it doesn't exist.
+pub async fn get_bytes(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+) -> Result<Option<Bytes>, ObjectError> {
+    match client.get_object().bucket(bucket).key(key).send().await {
+        Ok(o) => Ok(Some(o.body.collect().await?.into_bytes())),
+        Err(e) if matches!(e.as_service_error(), Some(GetObjectError::NoSuchKey(_))) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Fetch and parse a JSON object, or `None` if it doesn't exist.
+pub async fn load_json<T: DeserializeOwned>(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+) -> Result<Option<T>, ObjectError> {
+    match get_bytes(client, bucket, key).await? {
+        Some(data) => Ok(Some(serde_json::from_slice(&data)?)),
+        None => Ok(None),
+    }
+}
+
+pub async fn save_json<T: Serialize>(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    value: &T,
+) -> Result<(), ObjectError> {
+    let body = serde_json::to_vec(value)?;
+    client
+        .put_object()
+        .bucket(bucket)
+        .key(key)
+        .body(body.into())
+        .send()
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,79 @@
+use std::time::{Duration, Instant};
+
+use aws_sdk_s3::config::{retry::RetryConfig, timeout::TimeoutConfig};
+
+/// Give up on a read after this many failures in a row, unless overridden.
+pub const DEFAULT_MAX_READ_FAILURES: usize = 5;
+
+/// Settings for a single operation, overriding those of the client.
+///
+/// Fields left at `None` keep whatever the client (or the store the options
+/// are merged into) already uses.
+#[derive(Clone, Debug, Default)]
+pub struct OpOptions {
+    pub timeout: Option<TimeoutConfig>,
+    pub retry: Option<RetryConfig>,
+    /// Read failures in a row after which a download gives up.
+    pub max_read_failures: Option<usize>,
+    /// Limit the throughput of the operation.
+    pub max_bytes_per_second: Option<u64>,
+}
+
+impl OpOptions {
+    /// These options, with every field set in `overrides` replaced.
+    pub fn merge(&self, overrides: &OpOptions) -> OpOptions {
+        OpOptions {
+            timeout: overrides.timeout.clone().or_else(|| self.timeout.clone()),
+            retry: overrides.retry.clone().or_else(|| self.retry.clone()),
+            max_read_failures: overrides.max_read_failures.or(self.max_read_failures),
+            max_bytes_per_second: overrides.max_bytes_per_second.or(self.max_bytes_per_second),
+        }
+    }
+
+    pub(crate) fn config_override(&self) -> aws_sdk_s3::config::Builder {
+        let mut config = aws_sdk_s3::config::Builder::default();
+        config.set_timeout_config(self.timeout.clone());
+        config.set_retry_config(self.retry.clone());
+        config
+    }
+
+    pub(crate) fn max_read_failures(&self) -> usize {
+        self.max_read_failures.unwrap_or(DEFAULT_MAX_READ_FAILURES)
+    }
+
+    pub(crate) fn throttle(&self) -> Option<Throttle> {
+        self.max_bytes_per_second.map(Throttle::new)
+    }
+}
+
+/// Delays an operation so that its average throughput stays under a limit.
+pub(crate) struct Throttle {
+    bytes_per_second: u64,
+    start: Instant,
+    bytes: u64,
+}
+
+impl Throttle {
+    fn new(bytes_per_second: u64) -> Self {
+        Self {
+            bytes_per_second: bytes_per_second.max(1),
+            start: Instant::now(),
+            bytes: 0,
+        }
+    }
+
+    /// Account for `bytes` more, returning how long to wait before sending
+    /// them.
+    pub(crate) fn reserve(&mut self, bytes: usize) -> Duration {
+        self.bytes += bytes as u64;
+        let due = Duration::from_secs_f64(self.bytes as f64 / self.bytes_per_second as f64);
+        due.saturating_sub(self.start.elapsed())
+    }
+
+    pub(crate) async fn consume(&mut self, bytes: usize) {
+        let delay = self.reserve(bytes);
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
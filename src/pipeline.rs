@@ -0,0 +1,418 @@
+use std::collections::BTreeMap;
+use std::io::{self, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::pin::pin;
+use std::sync::{Arc, Mutex};
+
+use bytes::{Bytes, BytesMut};
+use futures::stream::StreamExt;
+use futures::Stream;
+use thiserror::Error;
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader};
+use tokio::sync::Notify;
+
+const SEGMENT_EXTENSION: &str = "seg";
+const CONSUMED_FILE: &str = "consumed";
+const FINISHED_FILE: &str = "finished";
+/// Every record is prefixed by its length as a little endian u64.
+const HEADER_SIZE: u64 = 8;
+
+#[derive(Clone, Debug)]
+pub struct PipelineConfig {
+    pub dir: PathBuf,
+    /// Start a new segment file once the current one reaches this size.
+    pub segment_size: u64,
+    /// Hold back the producer while the segments on disk take up this much
+    /// and the consumer still has something to read. Space is only freed by
+    /// [`PipelineConsumer::commit`].
+    pub max_bytes: u64,
+}
+
+#[derive(Debug, Error)]
+pub enum PipelineError<E> {
+    #[error("pipeline io failed: {0}")]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Source(E),
+}
+
+struct QueueState {
+    /// Records written, which is also the index of the next record.
+    written: u64,
+    /// Records the consumer has read.
+    read: u64,
+    finished: bool,
+    /// Size of each segment on disk, by the index of its first record.
+    segments: BTreeMap<u64, u64>,
+}
+
+impl QueueState {
+    fn bytes_on_disk(&self) -> u64 {
+        self.segments.values().sum()
+    }
+}
+
+struct Queue {
+    config: PipelineConfig,
+    state: Mutex<QueueState>,
+    written_notify: Notify,
+    space_notify: Notify,
+}
+
+impl Queue {
+    fn segment_path(&self, first: u64) -> PathBuf {
+        segment_path(&self.config.dir, first)
+    }
+}
+
+fn segment_path(dir: &Path, first: u64) -> PathBuf {
+    dir.join(format!("{first:020}.{SEGMENT_EXTENSION}"))
+}
+
+/// Count the complete records in a segment, returning the count and the
+/// length of the segment up to the last complete record.
+async fn scan_segment(path: &Path) -> io::Result<(u64, u64)> {
+    let file = File::open(path).await?;
+    let file_len = file.metadata().await?.len();
+    let mut reader = BufReader::new(file);
+    let mut count = 0;
+    let mut valid_len = 0;
+    loop {
+        if valid_len + HEADER_SIZE > file_len {
+            break;
+        }
+        let len = reader.read_u64_le().await?;
+        if valid_len + HEADER_SIZE + len > file_len {
+            break;
+        }
+        reader.seek(SeekFrom::Current(len as i64)).await?;
+        valid_len += HEADER_SIZE + len;
+        count += 1;
+    }
+
+    Ok((count, valid_len))
+}
+
+/// A bounded on-disk queue between a producer, such as a download, and a
+/// consumer.
+///
+/// Chunks are appended to segment files in `dir`. The consumer commits how
+/// far it got, after which fully consumed segments are removed. Both sides
+/// survive restarts: reopening the same directory continues reading from
+/// the last commit, and [`QueueWriter::written`] tells the producer where
+/// to resume its download.
+pub struct BufferedPipeline {
+    writer: QueueWriter,
+    consumer: PipelineConsumer,
+}
+
+impl BufferedPipeline {
+    pub async fn open(config: PipelineConfig) -> io::Result<Self> {
+        fs::create_dir_all(&config.dir).await?;
+
+        let consumed = match fs::read_to_string(config.dir.join(CONSUMED_FILE)).await {
+            Ok(s) => s
+                .trim()
+                .parse()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+        let finished = fs::try_exists(config.dir.join(FINISHED_FILE)).await?;
+
+        let mut segments = BTreeMap::new();
+        let mut entries = fs::read_dir(&config.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(SEGMENT_EXTENSION) {
+                continue;
+            }
+            let Some(first) = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(|s| s.parse::<u64>().ok())
+            else {
+                continue;
+            };
+            segments.insert(first, entry.metadata().await?.len());
+        }
+
+        // only the last segment can have been cut short
+        let mut written = consumed;
+        if let Some((&first, size)) = segments.iter_mut().next_back() {
+            let path = segment_path(&config.dir, first);
+            let (count, valid_len) = scan_segment(&path).await?;
+            if valid_len != *size {
+                OpenOptions::new()
+                    .write(true)
+                    .open(&path)
+                    .await?
+                    .set_len(valid_len)
+                    .await?;
+                *size = valid_len;
+            }
+            written = written.max(first + count);
+        }
+
+        let queue = Arc::new(Queue {
+            config,
+            state: Mutex::new(QueueState {
+                written,
+                read: consumed,
+                finished,
+                segments,
+            }),
+            written_notify: Notify::new(),
+            space_notify: Notify::new(),
+        });
+        remove_consumed_segments(&queue, consumed).await?;
+
+        Ok(Self {
+            writer: QueueWriter {
+                queue: queue.clone(),
+                segment: None,
+            },
+            consumer: PipelineConsumer {
+                queue,
+                position: consumed,
+                segment: None,
+            },
+        })
+    }
+
+    /// Records written so far, which is where a producer should resume.
+    pub fn written(&self) -> u64 {
+        self.writer.written()
+    }
+
+    pub fn split(self) -> (QueueWriter, PipelineConsumer) {
+        (self.writer, self.consumer)
+    }
+}
+
+/// Remove all segments whose records all come before `position`.
+async fn remove_consumed_segments(queue: &Queue, position: u64) -> io::Result<()> {
+    let removable: Vec<u64> = {
+        let state = queue.state.lock().unwrap();
+        let firsts: Vec<u64> = state.segments.keys().copied().collect();
+        firsts
+            .windows(2)
+            .filter(|w| w[1] <= position)
+            .map(|w| w[0])
+            .collect()
+    };
+    for first in removable {
+        match fs::remove_file(queue.segment_path(first)).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        queue.state.lock().unwrap().segments.remove(&first);
+    }
+    queue.space_notify.notify_waiters();
+
+    Ok(())
+}
+
+pub struct QueueWriter {
+    queue: Arc<Queue>,
+    /// The index of the first record of the open segment, and its file.
+    segment: Option<(u64, File)>,
+}
+
+impl QueueWriter {
+    pub fn written(&self) -> u64 {
+        self.queue.state.lock().unwrap().written
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.queue.state.lock().unwrap().finished
+    }
+
+    async fn wait_for_space(&self) {
+        loop {
+            let notified = self.queue.space_notify.notified();
+            let mut notified = pin!(notified);
+            notified.as_mut().enable();
+            {
+                let state = self.queue.state.lock().unwrap();
+                // a consumer that has read everything can't free up any
+                // more space until there's something new to read
+                if state.bytes_on_disk() < self.queue.config.max_bytes
+                    || state.read == state.written
+                {
+                    return;
+                }
+            }
+            notified.await;
+        }
+    }
+
+    /// Append a chunk, waiting for the consumer if the queue is full.
+    pub async fn push(&mut self, chunk: &[u8]) -> io::Result<()> {
+        self.wait_for_space().await;
+
+        let (written, current_size) = {
+            let state = self.queue.state.lock().unwrap();
+            let current_size = self
+                .segment
+                .as_ref()
+                .and_then(|(first, _)| state.segments.get(first).copied());
+            (state.written, current_size)
+        };
+        if current_size.is_none_or(|size| size >= self.queue.config.segment_size) {
+            if let Some((_, file)) = self.segment.take() {
+                file.sync_data().await?;
+            }
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.queue.segment_path(written))
+                .await?;
+            self.queue.state.lock().unwrap().segments.insert(written, 0);
+            self.segment = Some((written, file));
+        }
+
+        let (first, file) = self.segment.as_mut().expect("segment was just opened");
+        file.write_all(&(chunk.len() as u64).to_le_bytes()).await?;
+        file.write_all(chunk).await?;
+        file.flush().await?;
+
+        {
+            let mut state = self.queue.state.lock().unwrap();
+            state.written += 1;
+            *state.segments.entry(*first).or_default() += HEADER_SIZE + chunk.len() as u64;
+        }
+        self.queue.written_notify.notify_waiters();
+
+        Ok(())
+    }
+
+    /// Mark the queue as complete, so the consumer ends after the last
+    /// chunk rather than waiting for more.
+    pub async fn finish(mut self) -> io::Result<()> {
+        if let Some((_, file)) = self.segment.take() {
+            file.sync_data().await?;
+        }
+        File::create(self.queue.config.dir.join(FINISHED_FILE)).await?;
+        self.queue.state.lock().unwrap().finished = true;
+        self.queue.written_notify.notify_waiters();
+
+        Ok(())
+    }
+
+    /// Push every chunk of `source`, then finish.
+    pub async fn drain<S, E>(mut self, source: S) -> Result<(), PipelineError<E>>
+    where
+        S: Stream<Item = Result<Bytes, E>>,
+    {
+        if self.is_finished() {
+            return Ok(());
+        }
+        let mut source = pin!(source);
+        while let Some(chunk) = source.next().await {
+            let chunk = chunk.map_err(PipelineError::Source)?;
+            self.push(&chunk).await?;
+        }
+        self.finish().await?;
+
+        Ok(())
+    }
+}
+
+pub struct PipelineConsumer {
+    queue: Arc<Queue>,
+    /// The index of the next record to read.
+    position: u64,
+    /// The index of the first record of the segment being read, and a
+    /// reader positioned at `position` within it.
+    segment: Option<(u64, BufReader<File>)>,
+}
+
+impl PipelineConsumer {
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Wait until the record at `position` is written, returning the first
+    /// record of the segment it is in, or `None` if there are no more.
+    async fn wait_for_record(&self) -> Option<u64> {
+        loop {
+            let notified = self.queue.written_notify.notified();
+            let mut notified = pin!(notified);
+            notified.as_mut().enable();
+            {
+                let state = self.queue.state.lock().unwrap();
+                if self.position < state.written {
+                    let (first, _) = state
+                        .segments
+                        .range(..=self.position)
+                        .next_back()
+                        .expect("unconsumed records are in a segment");
+                    return Some(*first);
+                }
+                if state.finished {
+                    return None;
+                }
+            }
+            notified.await;
+        }
+    }
+
+    async fn open_segment(&mut self, first: u64) -> io::Result<()> {
+        let file = File::open(self.queue.segment_path(first)).await?;
+        let mut reader = BufReader::new(file);
+        for _ in first..self.position {
+            let len = reader.read_u64_le().await?;
+            reader.seek(SeekFrom::Current(len as i64)).await?;
+        }
+        self.segment = Some((first, reader));
+
+        Ok(())
+    }
+
+    /// The next chunk, or `None` once the producer finished and everything
+    /// was read.
+    pub async fn next(&mut self) -> Option<io::Result<Bytes>> {
+        let first = self.wait_for_record().await?;
+        if self.segment.as_ref().map(|(f, _)| *f) != Some(first) {
+            if let Err(e) = self.open_segment(first).await {
+                return Some(Err(e));
+            }
+        }
+
+        let (_, reader) = self.segment.as_mut().expect("segment was just opened");
+        let result = async {
+            let len = reader.read_u64_le().await?;
+            let mut data = BytesMut::zeroed(len as usize);
+            reader.read_exact(&mut data).await?;
+            Ok(data.freeze())
+        }
+        .await;
+        match result {
+            Ok(data) => {
+                self.position += 1;
+                self.queue.state.lock().unwrap().read = self.position;
+                self.queue.space_notify.notify_waiters();
+                Some(Ok(data))
+            }
+            Err(e) => {
+                // reopen on the next attempt
+                self.segment = None;
+                Some(Err(e))
+            }
+        }
+    }
+
+    /// Persist the position, so everything read so far is not read again
+    /// after a restart, and free the space it took up.
+    pub async fn commit(&mut self) -> io::Result<()> {
+        let dir = &self.queue.config.dir;
+        let tmp = dir.join(format!("{CONSUMED_FILE}.tmp"));
+        fs::write(&tmp, self.position.to_string()).await?;
+        fs::rename(&tmp, dir.join(CONSUMED_FILE)).await?;
+
+        remove_consumed_segments(&self.queue, self.position).await
+    }
+}
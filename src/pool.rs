@@ -0,0 +1,146 @@
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use tokio::sync::Semaphore;
+
+/// A source of reusable buffers, such as pinned host memory for GPU
+/// transfers.
+///
+/// Buffers handed out by `acquire` should be given back with `release` once
+/// they're no longer needed.
+#[async_trait]
+pub trait BufferPool: Send + Sync {
+    type Buffer: AsRef<[u8]> + AsMut<[u8]> + Send;
+
+    async fn acquire(&self) -> Self::Buffer;
+
+    fn release(&self, buffer: Self::Buffer) {
+        drop(buffer);
+    }
+}
+
+/// A pool of plain heap buffers of a fixed size.
+///
+/// At most `max_buffers` are handed out at a time, so `acquire` doubles as
+/// backpressure.
+pub struct VecPool {
+    buffer_size: usize,
+    free: Mutex<Vec<Vec<u8>>>,
+    available: Semaphore,
+}
+
+impl VecPool {
+    pub fn new(buffer_size: usize, max_buffers: usize) -> Self {
+        Self {
+            buffer_size,
+            free: Mutex::new(Vec::with_capacity(max_buffers)),
+            available: Semaphore::new(max_buffers),
+        }
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+}
+
+#[async_trait]
+impl BufferPool for VecPool {
+    type Buffer = Vec<u8>;
+
+    async fn acquire(&self) -> Vec<u8> {
+        self.available
+            .acquire()
+            .await
+            .expect("pool semaphore is never closed")
+            .forget();
+        let buffer = self.free.lock().unwrap().pop();
+
+        buffer.unwrap_or_else(|| vec![0; self.buffer_size])
+    }
+
+    fn release(&self, mut buffer: Vec<u8>) {
+        buffer.resize(self.buffer_size, 0);
+        self.free.lock().unwrap().push(buffer);
+        self.available.add_permits(1);
+    }
+}
+
+/// A buffer on its way back to its pool.
+///
+/// The first `len` bytes are the contents. Once this is dropped, the buffer
+/// is released back to the pool it came from.
+pub struct Pooled<P: BufferPool> {
+    pool: Arc<P>,
+    buffer: Option<P::Buffer>,
+    len: usize,
+}
+
+impl<P: BufferPool> Pooled<P> {
+    pub fn new(pool: Arc<P>, buffer: P::Buffer, len: usize) -> Self {
+        assert!(len <= buffer.as_ref().len());
+        Self {
+            pool,
+            buffer: Some(buffer),
+            len,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The whole buffer, including anything past the contents.
+    pub fn buffer_mut(&mut self) -> &mut [u8] {
+        self.buffer
+            .as_mut()
+            .expect("buffer is only taken on drop")
+            .as_mut()
+    }
+
+    pub fn set_len(&mut self, len: usize) {
+        assert!(len <= self.buffer_mut().len());
+        self.len = len;
+    }
+
+    /// Take the buffer out without releasing it, along with the length of
+    /// its contents.
+    pub fn into_inner(mut self) -> (P::Buffer, usize) {
+        let buffer = self.buffer.take().expect("buffer is only taken on drop");
+        (buffer, self.len)
+    }
+}
+
+impl<P> Pooled<P>
+where
+    P: BufferPool + 'static,
+    P::Buffer: 'static,
+{
+    /// Share the contents without copying. The buffer goes back to the pool
+    /// once the last clone of the returned `Bytes` is dropped.
+    pub fn into_bytes(self) -> Bytes {
+        Bytes::from_owner(self)
+    }
+}
+
+impl<P: BufferPool> AsRef<[u8]> for Pooled<P> {
+    fn as_ref(&self) -> &[u8] {
+        &self
+            .buffer
+            .as_ref()
+            .expect("buffer is only taken on drop")
+            .as_ref()[..self.len]
+    }
+}
+
+impl<P: BufferPool> Drop for Pooled<P> {
+    fn drop(&mut self) {
+        if let Some(buffer) = self.buffer.take() {
+            self.pool.release(buffer);
+        }
+    }
+}
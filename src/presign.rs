@@ -0,0 +1,212 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
+
+use aws_sdk_s3::config::ProvideCredentials;
+use aws_sdk_s3::presigning::PresigningConfig;
+use aws_sdk_s3::primitives::{DateTime, DateTimeFormat};
+use aws_sdk_s3::Client;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use thiserror::Error;
+
+use crate::names::{NameError, ObjectKey};
+
+#[derive(Debug, Error)]
+pub enum PresignError {
+    #[error("could not get credentials: {0}")]
+    CredentialsFailed(String),
+    #[error("client has no region configured")]
+    NoRegion,
+    #[error("could not resolve the bucket url: {0}")]
+    UrlFailed(String),
+    #[error(transparent)]
+    InvalidKey(#[from] NameError),
+}
+
+/// The object the sdk is asked a url for, to find the url of its bucket.
+const PLACEHOLDER_KEY: &str = "placeholder";
+
+/// What a browser upload through a POST policy is allowed to do.
+#[derive(Clone, Debug)]
+pub struct PostConditions {
+    pub expires_in: Duration,
+    /// Largest allowed upload, in bytes.
+    pub max_size: Option<u64>,
+    /// The exact content type the upload must have.
+    pub content_type: Option<String>,
+    /// A prefix the upload's content type must start with, like `image/`.
+    pub content_type_prefix: Option<String>,
+}
+
+impl PostConditions {
+    pub fn new(expires_in: Duration) -> Self {
+        Self {
+            expires_in,
+            max_size: None,
+            content_type: None,
+            content_type_prefix: None,
+        }
+    }
+
+    pub fn with_max_size(mut self, max_size: u64) -> Self {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    pub fn with_content_type(mut self, content_type: impl Into<String>) -> Self {
+        self.content_type = Some(content_type.into());
+        self
+    }
+
+    pub fn with_content_type_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.content_type_prefix = Some(prefix.into());
+        self
+    }
+}
+
+/// A signed POST policy: a form posted to `url` with `fields`, followed by
+/// the file, uploads it.
+#[derive(Clone, Debug)]
+pub struct PresignedPost {
+    pub url: String,
+    pub fields: BTreeMap<String, String>,
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac accepts keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// The SigV4 signature of a base64 encoded POST policy, for a request
+/// made on `date` (formatted like `20240102`).
+pub fn post_policy_signature(
+    secret_access_key: &str,
+    date: &str,
+    region: &str,
+    policy: &str,
+) -> String {
+    let key = format!("AWS4{secret_access_key}");
+    let key = hmac(key.as_bytes(), date.as_bytes());
+    let key = hmac(&key, region.as_bytes());
+    let key = hmac(&key, b"s3");
+    let key = hmac(&key, b"aws4_request");
+
+    hmac(&key, policy.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// The url to post to for uploading into `bucket`.
+///
+/// The sdk resolves where a bucket lives and whether it is addressed by
+/// host or by path, so this takes the url of a presigned request for some
+/// object and cuts the object off.
+pub async fn bucket_url(client: &Client, bucket: &str) -> Result<String, PresignError> {
+    let presigning =
+        PresigningConfig::expires_in(Duration::from_secs(60)).expect("a minute is a valid expiry");
+    let presigned = client
+        .put_object()
+        .bucket(bucket)
+        .key(PLACEHOLDER_KEY)
+        .presigned(presigning)
+        .await
+        .map_err(|e| PresignError::UrlFailed(e.to_string()))?;
+    let uri = presigned.uri();
+    let path = uri.split_once('?').map_or(uri, |(path, _)| path);
+
+    path.strip_suffix(PLACEHOLDER_KEY)
+        .map(str::to_string)
+        .ok_or_else(|| PresignError::UrlFailed(format!("unexpected url {uri}")))
+}
+
+/// Create a POST policy for uploading a single object under `key_prefix`.
+///
+/// The key is the prefix followed by the uploaded file's name. Region,
+/// endpoint and addressing style come from the configuration of `client`,
+/// so the form can be posted wherever the client would send its own
+/// requests. Credentials usually come from the same `SdkConfig` the client
+/// was built from.
+pub async fn presign_post(
+    client: &Client,
+    credentials: &impl ProvideCredentials,
+    bucket: &str,
+    key_prefix: &str,
+    conditions: &PostConditions,
+) -> Result<PresignedPost, PresignError> {
+    let key = format!("{key_prefix}${{filename}}");
+    ObjectKey::validate(&key)?;
+    let region = client
+        .config()
+        .region()
+        .ok_or(PresignError::NoRegion)?
+        .to_string();
+    let credentials = credentials
+        .provide_credentials()
+        .await
+        .map_err(|e| PresignError::CredentialsFailed(e.to_string()))?;
+    let url = bucket_url(client, bucket).await?;
+
+    let now = DateTime::from_secs(DateTime::from(SystemTime::now()).secs());
+    let expiration = DateTime::from_secs(now.secs() + conditions.expires_in.as_secs() as i64);
+    // 2024-01-02T03:04:05Z into 20240102T030405Z
+    let amz_date: String = now
+        .fmt(DateTimeFormat::DateTime)
+        .expect("current time is formattable")
+        .replace(['-', ':'], "");
+    let date = &amz_date[..8];
+    let credential = format!(
+        "{}/{date}/{region}/s3/aws4_request",
+        credentials.access_key_id()
+    );
+
+    let mut fields = BTreeMap::from([
+        ("key".to_string(), key),
+        (
+            "x-amz-algorithm".to_string(),
+            "AWS4-HMAC-SHA256".to_string(),
+        ),
+        ("x-amz-credential".to_string(), credential.clone()),
+        ("x-amz-date".to_string(), amz_date.clone()),
+    ]);
+
+    let mut policy_conditions: Vec<Value> = vec![
+        json!({ "bucket": bucket }),
+        json!(["starts-with", "$key", key_prefix]),
+        json!({ "x-amz-algorithm": "AWS4-HMAC-SHA256" }),
+        json!({ "x-amz-credential": credential }),
+        json!({ "x-amz-date": amz_date }),
+    ];
+    if let Some(token) = credentials.session_token() {
+        policy_conditions.push(json!({ "x-amz-security-token": token }));
+        fields.insert("x-amz-security-token".to_string(), token.to_string());
+    }
+    if let Some(max_size) = conditions.max_size {
+        policy_conditions.push(json!(["content-length-range", 0, max_size]));
+    }
+    if let Some(content_type) = conditions.content_type.as_ref() {
+        policy_conditions.push(json!({ "Content-Type": content_type }));
+        fields.insert("Content-Type".to_string(), content_type.clone());
+    }
+    if let Some(prefix) = conditions.content_type_prefix.as_ref() {
+        policy_conditions.push(json!(["starts-with", "$Content-Type", prefix]));
+    }
+
+    let policy = json!({
+        "expiration": expiration
+            .fmt(DateTimeFormat::DateTime)
+            .expect("expiration is formattable"),
+        "conditions": policy_conditions,
+    });
+    let policy = base64::engine::general_purpose::STANDARD.encode(policy.to_string());
+
+    let signature = post_policy_signature(credentials.secret_access_key(), date, &region, &policy);
+
+    fields.insert("policy".to_string(), policy);
+    fields.insert("x-amz-signature".to_string(), signature);
+
+    Ok(PresignedPost { url, fields })
+}
@@ -0,0 +1,133 @@
+use std::{collections::BTreeMap, sync::Mutex};
+
+use aws_sdk_s3::Client;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::object::{self, ObjectError};
+
+#[derive(Debug, Error)]
+#[error("quota exceeded for {prefix:?}: {used} of {limit} bytes used, {requested} more requested")]
+pub struct QuotaExceeded {
+    pub prefix: String,
+    pub limit: usize,
+    pub used: usize,
+    pub requested: usize,
+}
+
+pub type QuotaStateError = ObjectError;
+
+/// Bytes written per prefix.
+///
+/// This is small enough to be persisted as a single object, so that usage
+/// survives restarts.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct QuotaState {
+    pub usage: BTreeMap<String, usize>,
+}
+
+impl QuotaState {
+    /// Load the state from an object, returning an empty state if the
+    /// object does not exist yet.
+    pub async fn load(client: &Client, bucket: &str, key: &str) -> Result<Self, QuotaStateError> {
+        Ok(object::load_json(client, bucket, key)
+            .await?
+            .unwrap_or_default())
+    }
+
+    pub async fn save(
+        &self,
+        client: &Client,
+        bucket: &str,
+        key: &str,
+    ) -> Result<(), QuotaStateError> {
+        object::save_json(client, bucket, key, self).await
+    }
+}
+
+/// Byte limits on key prefixes.
+///
+/// A key counts towards every configured prefix it starts with. Usage is
+/// charged when data is handed to the crate, before it is sent, so that a
+/// write exceeding the limit never reaches the bucket. Stores release
+/// usage again for deleted, overwritten and garbage collected objects, and
+/// uploads for parts that failed.
+///
+/// Usage is only kept in memory. Nothing persists it automatically, so
+/// applications that need it to survive restarts should save `state()`
+/// with `QuotaState::save` periodically and on shutdown, and load it again
+/// with `from_state`.
+pub struct Quota {
+    limits: BTreeMap<String, usize>,
+    state: Mutex<QuotaState>,
+}
+
+impl Quota {
+    pub fn new(limits: impl IntoIterator<Item = (String, usize)>) -> Self {
+        Self::from_state(limits, QuotaState::default())
+    }
+
+    pub fn from_state(
+        limits: impl IntoIterator<Item = (String, usize)>,
+        state: QuotaState,
+    ) -> Self {
+        Self {
+            limits: limits.into_iter().collect(),
+            state: Mutex::new(state),
+        }
+    }
+
+    /// A snapshot of the current usage, for persisting.
+    pub fn state(&self) -> QuotaState {
+        self.state.lock().unwrap().clone()
+    }
+
+    pub fn usage(&self, prefix: &str) -> usize {
+        self.state
+            .lock()
+            .unwrap()
+            .usage
+            .get(prefix)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    fn matching<'a>(&'a self, key: &'a str) -> impl Iterator<Item = (&'a String, &'a usize)> {
+        self.limits
+            .iter()
+            .filter(move |(prefix, _)| key.starts_with(prefix.as_str()))
+    }
+
+    /// Charge `bytes` written to `key` against every matching prefix, or
+    /// charge nothing at all if any of them would go over its limit.
+    pub fn charge(&self, key: &str, bytes: usize) -> Result<(), QuotaExceeded> {
+        let mut state = self.state.lock().unwrap();
+        for (prefix, limit) in self.matching(key) {
+            let used = state.usage.get(prefix).copied().unwrap_or(0);
+            if used + bytes > *limit {
+                return Err(QuotaExceeded {
+                    prefix: prefix.clone(),
+                    limit: *limit,
+                    used,
+                    requested: bytes,
+                });
+            }
+        }
+        for (prefix, _) in self.matching(key) {
+            *state.usage.entry(prefix.clone()).or_default() += bytes;
+        }
+
+        Ok(())
+    }
+
+    /// Give back bytes that were charged but are no longer stored, because
+    /// they were never written or have since been deleted.
+    pub fn release(&self, key: &str, bytes: usize) {
+        let mut state = self.state.lock().unwrap();
+        for (prefix, _) in self.matching(key) {
+            if let Some(used) = state.usage.get_mut(prefix) {
+                *used = used.saturating_sub(bytes);
+            }
+        }
+    }
+}
@@ -0,0 +1,357 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::{ready, Context, Poll},
+};
+
+use aws_sdk_s3::{
+    config::{http::HttpRequest, RuntimeComponents},
+    primitives::SdkBody,
+};
+use aws_smithy_http_client::test_util::dvr::{Event as RecordedEvent, ReplayingClient};
+use aws_smithy_http_client::tls::{self, rustls_provider::CryptoMode};
+use aws_smithy_http_client::Connector;
+use aws_smithy_runtime_api::client::{
+    connector_metadata::ConnectorMetadata,
+    http::{
+        HttpClient, HttpConnector, HttpConnectorFuture, HttpConnectorSettings, SharedHttpConnector,
+    },
+};
+use aws_smithy_runtime_api::http::Headers;
+use aws_smithy_runtime_api::shared::IntoShared;
+use aws_smithy_types::body::Error as BodyError;
+use base64::Engine;
+use bytes::Bytes;
+use http_body::{Body, Frame, SizeHint};
+use serde::Serialize;
+use thiserror::Error;
+
+/// Request headers that carry credentials. Their values are never written
+/// to a recording.
+pub const REDACTED_HEADERS: &[&str] = &["authorization", "x-amz-security-token"];
+
+#[derive(Debug, Error)]
+pub enum ReplayError {
+    #[error("could not write recording: {0}")]
+    WriteFailed(#[from] std::io::Error),
+    #[error("could not load recording: {0}")]
+    LoadFailed(String),
+}
+
+/// Captures every request and response made by clients built with it.
+///
+/// Events are appended to the recording file as they happen, one JSON
+/// event per line, so nothing accumulates in memory. Credential headers
+/// are redacted. Request and response bodies are recorded in full unless
+/// left out with `without_bodies`.
+#[derive(Clone, Debug)]
+pub struct Recorder {
+    inner: SharedHttpConnector,
+    sink: Arc<EventSink>,
+    next_id: Arc<AtomicUsize>,
+    record_bodies: bool,
+}
+
+impl Recorder {
+    /// Start a recording in `path`, replacing whatever is there.
+    pub fn new(path: impl AsRef<Path>) -> Result<Self, ReplayError> {
+        let connector = Connector::builder()
+            .tls_provider(tls::Provider::Rustls(CryptoMode::AwsLc))
+            .build();
+        Self::with_connector(path, connector)
+    }
+
+    /// Record the traffic going through `connector` rather than a default
+    /// https connector.
+    pub fn with_connector(
+        path: impl AsRef<Path>,
+        connector: impl HttpConnector + 'static,
+    ) -> Result<Self, ReplayError> {
+        let sink = EventSink {
+            file: Mutex::new(BufWriter::new(File::create(path)?)),
+            error: Mutex::new(None),
+        };
+        Ok(Self {
+            inner: connector.into_shared(),
+            sink: Arc::new(sink),
+            next_id: Arc::new(AtomicUsize::new(0)),
+            record_bodies: true,
+        })
+    }
+
+    /// Leave request and response bodies out of the recording.
+    ///
+    /// Response content lengths are recorded as 0 then, so a replay of the
+    /// recording consistently answers with empty bodies.
+    pub fn without_bodies(mut self) -> Self {
+        self.record_bodies = false;
+        self
+    }
+
+    /// Flush the recording, reporting the first write that failed, if any.
+    ///
+    /// Only body data that has actually been read is included, so call this
+    /// once all downloads are done.
+    pub fn finish(&self) -> Result<(), ReplayError> {
+        if let Some(error) = self.sink.error.lock().unwrap().take() {
+            return Err(error.into());
+        }
+
+        Ok(self.sink.file.lock().unwrap().flush()?)
+    }
+
+    fn record(&self, connection_id: usize, action: Action) {
+        self.sink.write(&Event {
+            connection_id,
+            action,
+        });
+    }
+
+    fn record_body(&self, body: &mut SdkBody, connection_id: usize, direction: Direction) {
+        let inner = std::mem::replace(body, SdkBody::taken());
+        *body = SdkBody::from_body_1_x(RecordedBody {
+            inner,
+            recorder: self.clone(),
+            connection_id,
+            direction,
+            done: false,
+        });
+    }
+}
+
+impl HttpConnector for Recorder {
+    fn call(&self, mut request: HttpRequest) -> HttpConnectorFuture {
+        let connection_id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let recorded = Request {
+            uri: request.uri().to_string(),
+            headers: recorded_headers(request.headers(), |name| {
+                REDACTED_HEADERS.contains(&name).then_some("<redacted>")
+            }),
+            method: request.method().to_string(),
+        };
+        self.record(connection_id, Action::Request { request: recorded });
+        self.record_body(request.body_mut(), connection_id, Direction::Request);
+
+        let recorder = self.clone();
+        let response = self.inner.call(request);
+        HttpConnectorFuture::new(async move {
+            match response.await {
+                Ok(mut response) => {
+                    let recorded = Response {
+                        status: response.status().into(),
+                        headers: recorded_headers(response.headers(), |name| {
+                            (!recorder.record_bodies && name == "content-length").then_some("0")
+                        }),
+                    };
+                    recorder.record(
+                        connection_id,
+                        Action::Response {
+                            response: Ok(recorded),
+                        },
+                    );
+                    recorder.record_body(response.body_mut(), connection_id, Direction::Response);
+                    Ok(response)
+                }
+                Err(e) => {
+                    recorder.record(
+                        connection_id,
+                        Action::Response {
+                            response: Err(e.to_string()),
+                        },
+                    );
+                    Err(e)
+                }
+            }
+        })
+    }
+}
+
+impl HttpClient for Recorder {
+    fn http_connector(
+        &self,
+        _settings: &HttpConnectorSettings,
+        _components: &RuntimeComponents,
+    ) -> SharedHttpConnector {
+        self.clone().into_shared()
+    }
+
+    fn connector_metadata(&self) -> Option<ConnectorMetadata> {
+        Some(ConnectorMetadata::new("recorder", None))
+    }
+}
+
+/// Load a recording made by a `Recorder`, to answer requests from.
+///
+/// Responses are played back per connection in the order they were
+/// recorded, without any network access.
+pub(crate) fn load_recording(path: impl AsRef<Path>) -> Result<ReplayingClient, ReplayError> {
+    let file = File::open(path).map_err(|e| ReplayError::LoadFailed(e.to_string()))?;
+    let mut events = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(|e| ReplayError::LoadFailed(e.to_string()))?;
+        if line.is_empty() {
+            continue;
+        }
+        let event: RecordedEvent =
+            serde_json::from_str(&line).map_err(|e| ReplayError::LoadFailed(e.to_string()))?;
+        events.push(event);
+    }
+
+    Ok(ReplayingClient::new(events))
+}
+
+#[derive(Debug)]
+struct EventSink {
+    file: Mutex<BufWriter<File>>,
+    /// The first write that failed. Recording carries on regardless, as
+    /// the traffic itself shouldn't fail over it.
+    error: Mutex<Option<io::Error>>,
+}
+
+impl EventSink {
+    fn write(&self, event: &Event) {
+        let mut line = serde_json::to_vec(event).expect("events always serialize");
+        line.push(b'\n');
+        if let Err(e) = self.file.lock().unwrap().write_all(&line) {
+            self.error.lock().unwrap().get_or_insert(e);
+        }
+    }
+}
+
+/// A body passing its frames through while recording them.
+struct RecordedBody {
+    inner: SdkBody,
+    recorder: Recorder,
+    connection_id: usize,
+    direction: Direction,
+    done: bool,
+}
+
+impl Body for RecordedBody {
+    type Data = Bytes;
+    type Error = BodyError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, BodyError>>> {
+        let this = self.get_mut();
+        let frame = ready!(Pin::new(&mut this.inner).poll_frame(cx));
+        let action = match &frame {
+            Some(Ok(frame)) => match frame.data_ref() {
+                Some(data) if this.recorder.record_bodies => Some(Action::Data {
+                    data: BodyData::from(data),
+                    direction: this.direction,
+                }),
+                _ => None,
+            },
+            Some(Err(_)) => Some(Action::Eof {
+                ok: false,
+                direction: this.direction,
+            }),
+            None => Some(Action::Eof {
+                ok: true,
+                direction: this.direction,
+            }),
+        };
+        if !matches!(frame, Some(Ok(_))) {
+            this.done = true;
+        }
+        if let Some(action) = action {
+            this.recorder.record(this.connection_id, action);
+        }
+
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        // an empty body still has to be polled once, to record its end
+        self.done
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        Body::size_hint(&self.inner)
+    }
+}
+
+fn recorded_headers(
+    headers: &Headers,
+    replace: impl Fn(&str) -> Option<&'static str>,
+) -> BTreeMap<String, Vec<String>> {
+    let mut recorded: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (name, value) in headers.iter() {
+        let name = name.to_ascii_lowercase();
+        let value = replace(&name).unwrap_or(value).to_string();
+        recorded.entry(name).or_default().push(value);
+    }
+
+    recorded
+}
+
+// The types below serialize the same way as those of the smithy dvr, so
+// that its replaying client can load recordings.
+
+#[derive(Serialize)]
+struct Event {
+    connection_id: usize,
+    action: Action,
+}
+
+#[derive(Serialize)]
+enum Action {
+    Request {
+        request: Request,
+    },
+    Response {
+        response: Result<Response, String>,
+    },
+    Data {
+        data: BodyData,
+        direction: Direction,
+    },
+    Eof {
+        ok: bool,
+        direction: Direction,
+    },
+}
+
+#[derive(Serialize)]
+struct Request {
+    uri: String,
+    headers: BTreeMap<String, Vec<String>>,
+    method: String,
+}
+
+#[derive(Serialize)]
+struct Response {
+    status: u16,
+    headers: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Serialize)]
+enum BodyData {
+    Utf8(String),
+    Base64(String),
+}
+
+impl From<&Bytes> for BodyData {
+    fn from(data: &Bytes) -> Self {
+        match std::str::from_utf8(data) {
+            Ok(string) => BodyData::Utf8(string.to_string()),
+            Err(_) => BodyData::Base64(base64::engine::general_purpose::STANDARD.encode(data)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Serialize)]
+enum Direction {
+    Request,
+    Response,
+}
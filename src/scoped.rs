@@ -0,0 +1,393 @@
+use std::sync::Arc;
+
+use aws_sdk_s3::{operation::head_object::HeadObjectOutput, primitives::DateTime};
+use bytes::Bytes;
+use futures::Stream;
+use thiserror::Error;
+
+use crate::{
+    compress::{CompressionError, Dictionary, DictionaryStore},
+    download::{AsOfError, DownloadVecError, HeadError, VecStreamError},
+    gc::{GcError, GcOptions, GcReport},
+    manifest::Manifest,
+    names::{NameError, ObjectKey},
+    options::OpOptions,
+    store::{S3Store, StoreCopyError, StoreDeleteError, StorePutError},
+    upload::{Upload, UploadCreateError, Uploads},
+};
+
+/// Rewrites a validated key fragment before it gets prefixed.
+pub type KeyRewrite = Arc<dyn Fn(&str) -> String + Send + Sync>;
+
+#[derive(Debug, Error)]
+pub enum ScopeError {
+    #[error("key is empty")]
+    EmptyKey,
+    #[error("key {0:?} is absolute")]
+    AbsoluteKey(String),
+    #[error("key {0:?} contains a path traversal segment")]
+    PathTraversal(String),
+    #[error("key {0:?} contains an empty path segment")]
+    EmptySegment(String),
+    #[error(transparent)]
+    InvalidKey(#[from] NameError),
+}
+
+#[derive(Debug, Error)]
+pub enum ScopedError<E> {
+    #[error(transparent)]
+    Scope(#[from] ScopeError),
+    #[error(transparent)]
+    Store(E),
+}
+
+/// Checks that a key fragment can't escape the prefix it is joined to.
+///
+/// A single trailing slash is allowed, so that fragments can be used as
+/// prefixes themselves.
+pub fn validate_fragment(fragment: &str) -> Result<(), ScopeError> {
+    if fragment.is_empty() {
+        return Err(ScopeError::EmptyKey);
+    }
+    if fragment.starts_with('/') || fragment.starts_with('\\') {
+        return Err(ScopeError::AbsoluteKey(fragment.to_string()));
+    }
+
+    let trimmed = fragment
+        .strip_suffix('/')
+        .or_else(|| fragment.strip_suffix('\\'))
+        .unwrap_or(fragment);
+    for segment in trimmed.split(['/', '\\']) {
+        match segment {
+            "" => return Err(ScopeError::EmptySegment(fragment.to_string())),
+            "." | ".." => return Err(ScopeError::PathTraversal(fragment.to_string())),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// A store restricted to keys under a fixed prefix.
+///
+/// Every key handed to a `ScopedStore` is treated as untrusted and is
+/// validated with [`validate_fragment`] before being joined to the prefix.
+#[derive(Clone)]
+pub struct ScopedStore {
+    store: S3Store,
+    prefix: String,
+    rewrite: Option<KeyRewrite>,
+}
+
+impl ScopedStore {
+    pub fn new(store: S3Store, prefix: impl Into<String>) -> Result<Self, ScopeError> {
+        let mut prefix = prefix.into();
+        validate_fragment(&prefix)?;
+        if !prefix.ends_with('/') {
+            prefix.push('/');
+        }
+
+        Ok(Self {
+            store,
+            prefix,
+            rewrite: None,
+        })
+    }
+
+    /// Rewrite every key fragment before it is prefixed. The rewritten
+    /// fragment is validated again, so a rewrite can't escape the scope
+    /// either.
+    pub fn with_key_rewrite(
+        mut self,
+        rewrite: impl Fn(&str) -> String + Send + Sync + 'static,
+    ) -> Self {
+        self.rewrite = Some(Arc::new(rewrite));
+        self
+    }
+
+    /// Narrow this scope down further.
+    pub fn subscope(&self, fragment: &str) -> Result<Self, ScopeError> {
+        let prefix = self.key(fragment)?;
+        Ok(Self {
+            store: self.store.clone(),
+            prefix: if prefix.ends_with('/') {
+                prefix
+            } else {
+                format!("{prefix}/")
+            },
+            rewrite: self.rewrite.clone(),
+        })
+    }
+
+    /// A copy of this scope whose operations use `overrides` over the
+    /// settings of its store.
+    pub fn with_op_options(&self, overrides: &OpOptions) -> Self {
+        Self {
+            store: self.store.with_op_options(overrides),
+            prefix: self.prefix.clone(),
+            rewrite: self.rewrite.clone(),
+        }
+    }
+
+    pub fn store(&self) -> &S3Store {
+        &self.store
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Turns an untrusted key fragment into a full key within this scope.
+    pub fn key(&self, fragment: &str) -> Result<String, ScopeError> {
+        validate_fragment(fragment)?;
+        let key = match self.rewrite.as_ref() {
+            Some(rewrite) => {
+                let rewritten = rewrite(fragment);
+                validate_fragment(&rewritten)?;
+                format!("{}{rewritten}", self.prefix)
+            }
+            None => format!("{}{fragment}", self.prefix),
+        };
+
+        Ok(ObjectKey::new(key)?.into())
+    }
+
+    pub async fn download_vec<T: Copy + Default>(
+        &self,
+        key: &str,
+    ) -> Result<Vec<T>, ScopedError<DownloadVecError>> {
+        let key = self.key(key)?;
+        self.store
+            .download_vec(&key)
+            .await
+            .map_err(ScopedError::Store)
+    }
+
+    pub async fn try_download_vec<T: Copy + Default>(
+        &self,
+        key: &str,
+    ) -> Result<Option<Vec<T>>, ScopedError<DownloadVecError>> {
+        let key = self.key(key)?;
+        self.store
+            .try_download_vec(&key)
+            .await
+            .map_err(ScopedError::Store)
+    }
+
+    pub async fn head(&self, key: &str) -> Result<HeadObjectOutput, ScopedError<HeadError>> {
+        let key = self.key(key)?;
+        self.store.head(&key).await.map_err(ScopedError::Store)
+    }
+
+    pub async fn try_head(
+        &self,
+        key: &str,
+    ) -> Result<Option<HeadObjectOutput>, ScopedError<HeadError>> {
+        let key = self.key(key)?;
+        self.store.try_head(&key).await.map_err(ScopedError::Store)
+    }
+
+    pub async fn stream_vecs_from(
+        &self,
+        key: &str,
+        start_index: usize,
+        end_index: Option<usize>,
+        chunk_size: usize,
+    ) -> Result<impl Stream<Item = Result<Bytes, VecStreamError>>, ScopeError> {
+        let key = self.key(key)?;
+        Ok(self
+            .store
+            .stream_vecs_from(key, start_index, end_index, chunk_size)
+            .await)
+    }
+
+    pub async fn try_stream_vecs_from(
+        &self,
+        key: &str,
+        start_index: usize,
+        end_index: Option<usize>,
+        chunk_size: usize,
+    ) -> Result<
+        Option<impl Stream<Item = Result<Bytes, VecStreamError>>>,
+        ScopedError<VecStreamError>,
+    > {
+        let key = self.key(key)?;
+        self.store
+            .try_stream_vecs_from(key, start_index, end_index, chunk_size)
+            .await
+            .map_err(ScopedError::Store)
+    }
+
+    pub async fn concurrent_stream_vecs_from(
+        &self,
+        key: &str,
+        start_index: usize,
+        end_index: Option<usize>,
+        chunk_size: usize,
+    ) -> Result<impl Stream<Item = Result<Bytes, VecStreamError>>, ScopeError> {
+        let key = self.key(key)?;
+        Ok(self
+            .store
+            .concurrent_stream_vecs_from(key, start_index, end_index, chunk_size)
+            .await)
+    }
+
+    pub async fn stream_vecs_as_of(
+        &self,
+        key: &str,
+        timestamp: DateTime,
+        chunk_size: usize,
+    ) -> Result<impl Stream<Item = Result<Bytes, VecStreamError>>, ScopedError<AsOfError>> {
+        let key = self.key(key)?;
+        self.store
+            .stream_vecs_as_of(key, timestamp, chunk_size)
+            .await
+            .map_err(ScopedError::Store)
+    }
+
+    pub async fn put_object(
+        &self,
+        key: &str,
+        data: Bytes,
+    ) -> Result<(), ScopedError<StorePutError>> {
+        let key = self.key(key)?;
+        self.store
+            .put_object(key, data)
+            .await
+            .map_err(ScopedError::Store)
+    }
+
+    pub async fn put_compressed(
+        &self,
+        key: &str,
+        data: &[u8],
+        dictionary: Option<&Dictionary>,
+        level: i32,
+    ) -> Result<(), ScopedError<CompressionError>> {
+        let key = self.key(key)?;
+        self.store
+            .put_compressed(key, data, dictionary, level)
+            .await
+            .map_err(ScopedError::Store)
+    }
+
+    pub async fn get_decompressed(
+        &self,
+        key: &str,
+        dictionaries: &DictionaryStore,
+    ) -> Result<Vec<u8>, ScopedError<CompressionError>> {
+        let key = self.key(key)?;
+        self.store
+            .get_decompressed(&key, dictionaries)
+            .await
+            .map_err(ScopedError::Store)
+    }
+
+    /// Dictionaries stored within this scope under `prefix`.
+    pub fn dictionaries(&self, prefix: &str) -> Result<DictionaryStore, ScopeError> {
+        Ok(self.store.dictionaries(self.key(prefix)?))
+    }
+
+    pub async fn delete_object(&self, key: &str) -> Result<(), ScopedError<StoreDeleteError>> {
+        let key = self.key(key)?;
+        self.store
+            .delete_object(key)
+            .await
+            .map_err(ScopedError::Store)
+    }
+
+    /// Copy an object within this scope.
+    pub async fn copy_object(
+        &self,
+        source_key: &str,
+        key: &str,
+    ) -> Result<(), ScopedError<StoreCopyError>> {
+        let source_key = self.key(source_key)?;
+        let key = self.key(key)?;
+        self.store
+            .copy_object(source_key, key)
+            .await
+            .map_err(ScopedError::Store)
+    }
+
+    /// Delete everything under `prefix` within this scope not referenced by
+    /// `manifests`, whose keys are relative to this scope as well.
+    pub async fn gc_prefix(
+        &self,
+        prefix: &str,
+        manifests: &[Manifest],
+        mut options: GcOptions,
+    ) -> Result<GcReport, ScopedError<GcError>> {
+        let prefix = self.key(prefix)?;
+        let mut scoped_manifests = Vec::with_capacity(manifests.len());
+        for manifest in manifests {
+            let mut manifest = manifest.clone();
+            for shard in manifest.shards.iter_mut() {
+                shard.key = self.key(&shard.key)?;
+            }
+            scoped_manifests.push(manifest);
+        }
+        options.keep = options
+            .keep
+            .iter()
+            .map(|key| self.key(key))
+            .collect::<Result<_, _>>()?;
+
+        self.store
+            .gc_prefix(&prefix, &scoped_manifests, options)
+            .await
+            .map_err(ScopedError::Store)
+    }
+
+    pub async fn upload(&self, key: &str) -> Result<Upload, ScopedError<UploadCreateError>> {
+        let key = self.key(key)?;
+        self.store.upload(key).await.map_err(ScopedError::Store)
+    }
+
+    pub async fn upload_with_size(
+        &self,
+        key: &str,
+        size_per_upload: usize,
+    ) -> Result<Upload, ScopedError<UploadCreateError>> {
+        let key = self.key(key)?;
+        self.store
+            .upload_with_size(key, size_per_upload)
+            .await
+            .map_err(ScopedError::Store)
+    }
+
+    pub async fn uploads(
+        &self,
+        prefix: &str,
+        amount: usize,
+    ) -> Result<Uploads, ScopedError<UploadCreateError>> {
+        let mut uploads = Vec::with_capacity(amount);
+        for index in 0..amount {
+            let key = self.key(&format!("{prefix}{index}"))?;
+            let upload = self.store.upload(key).await.map_err(ScopedError::Store)?;
+            uploads.push(upload);
+        }
+
+        Ok(Uploads::from_uploads(uploads))
+    }
+
+    pub async fn uploads_with_size(
+        &self,
+        prefix: &str,
+        amount: usize,
+        size_per_upload: usize,
+    ) -> Result<Uploads, ScopedError<UploadCreateError>> {
+        let mut uploads = Vec::with_capacity(amount);
+        for index in 0..amount {
+            let key = self.key(&format!("{prefix}{index}"))?;
+            let upload = self
+                .store
+                .upload_with_size(key, size_per_upload)
+                .await
+                .map_err(ScopedError::Store)?;
+            uploads.push(upload);
+        }
+
+        Ok(Uploads::from_uploads(uploads))
+    }
+}
@@ -0,0 +1,262 @@
+use std::pin::Pin;
+use std::sync::Arc;
+
+use aws_sdk_s3::error::SdkError;
+use aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Error;
+use bytes::Bytes;
+use futures::stream::StreamExt;
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::download::{stream_vecs_from_with_options, VecStreamError};
+use crate::manifest::Manifest;
+use crate::names::{NameError, ObjectKey};
+use crate::options::OpOptions;
+
+#[derive(Debug, Error)]
+pub enum SessionError {
+    #[error(transparent)]
+    InvalidKey(#[from] NameError),
+    #[error("could not list objects: {0}")]
+    ListFailed(#[from] SdkError<ListObjectsV2Error>),
+    #[error("chunk size must be positive")]
+    ZeroChunkSize,
+    #[error("size {size} of {key} is not a multiple of the chunk size {chunk_size}")]
+    UnalignedSize {
+        key: String,
+        size: usize,
+        chunk_size: usize,
+    },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KeyProgress {
+    pub key: String,
+    /// The size of the object, if known up front.
+    pub size: Option<usize>,
+    pub bytes_read: usize,
+    pub done: bool,
+}
+
+/// Everything needed to resume a [`DownloadSession`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DownloadCursor {
+    pub bucket: String,
+    pub chunk_size: usize,
+    pub keys: Vec<KeyProgress>,
+}
+
+impl DownloadCursor {
+    pub fn is_done(&self) -> bool {
+        self.keys.iter().all(|k| k.done)
+    }
+
+    pub fn bytes_read(&self) -> usize {
+        self.keys.iter().map(|k| k.bytes_read).sum()
+    }
+
+    /// Check that every key is valid and every known size is made up of
+    /// whole chunks, as nothing past the last whole chunk of a key is ever
+    /// read.
+    pub fn validate(&self) -> Result<(), SessionError> {
+        if self.chunk_size == 0 {
+            return Err(SessionError::ZeroChunkSize);
+        }
+        for progress in self.keys.iter() {
+            ObjectKey::validate(&progress.key)?;
+            if let Some(size) = progress.size {
+                if size % self.chunk_size != 0 {
+                    return Err(SessionError::UnalignedSize {
+                        key: progress.key.clone(),
+                        size,
+                        chunk_size: self.chunk_size,
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SessionChunk {
+    /// The position of the key in the session.
+    pub key_index: usize,
+    /// The index of this chunk within its key.
+    pub index: usize,
+    pub data: Bytes,
+}
+
+type ChunkStream = Pin<Box<dyn Stream<Item = Result<Bytes, VecStreamError>> + Send>>;
+
+/// Downloads multiple keys in order, tracking how far along each key it is.
+///
+/// The cursor is advanced as chunks are handed out, so persisting it after
+/// processing a chunk means a restarted worker continues with the next one.
+pub struct DownloadSession {
+    client: Arc<aws_sdk_s3::Client>,
+    cursor: DownloadCursor,
+    current: Option<(usize, ChunkStream)>,
+    options: OpOptions,
+}
+
+impl DownloadSession {
+    /// Continue a session from its cursor, which is validated first as it
+    /// usually comes from outside, like a file or a manifest.
+    pub fn resume(
+        client: Arc<aws_sdk_s3::Client>,
+        cursor: DownloadCursor,
+    ) -> Result<Self, SessionError> {
+        cursor.validate()?;
+
+        Ok(Self {
+            client,
+            cursor,
+            current: None,
+            options: OpOptions::default(),
+        })
+    }
+
+    /// Override the client settings for every request this session makes.
+    pub fn with_options(mut self, options: OpOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn from_manifest(
+        client: Arc<aws_sdk_s3::Client>,
+        bucket: String,
+        manifest: &Manifest,
+    ) -> Result<Self, SessionError> {
+        let keys = manifest
+            .shards
+            .iter()
+            .map(|shard| KeyProgress {
+                key: shard.key.clone(),
+                size: Some(shard.count * manifest.chunk_size),
+                bytes_read: 0,
+                done: shard.count == 0,
+            })
+            .collect();
+        let cursor = DownloadCursor {
+            bucket,
+            chunk_size: manifest.chunk_size,
+            keys,
+        };
+
+        Self::resume(client, cursor)
+    }
+
+    /// A session over all keys under a prefix, in listing order.
+    ///
+    /// Every object must consist of whole chunks.
+    pub async fn from_prefix(
+        client: Arc<aws_sdk_s3::Client>,
+        bucket: String,
+        prefix: &str,
+        chunk_size: usize,
+    ) -> Result<Self, SessionError> {
+        let mut keys = Vec::new();
+        let mut pages = client
+            .list_objects_v2()
+            .bucket(&bucket)
+            .prefix(prefix)
+            .into_paginator()
+            .send();
+        while let Some(page) = pages.next().await {
+            for object in page?.contents() {
+                let Some(key) = object.key() else {
+                    continue;
+                };
+                let size = object.size().map(|s| s as usize);
+                keys.push(KeyProgress {
+                    key: key.to_string(),
+                    size,
+                    bytes_read: 0,
+                    done: size == Some(0),
+                });
+            }
+        }
+        let cursor = DownloadCursor {
+            bucket,
+            chunk_size,
+            keys,
+        };
+        Self::resume(client, cursor)
+    }
+
+    pub fn cursor(&self) -> &DownloadCursor {
+        &self.cursor
+    }
+
+    /// Open the stream for a key, or `None` if there is nothing left to read
+    /// from it.
+    async fn open(&self, key_index: usize) -> Option<ChunkStream> {
+        let chunk_size = self.cursor.chunk_size;
+        let progress = &self.cursor.keys[key_index];
+        let start_index = progress.bytes_read / chunk_size;
+        let end_index = progress.size.map(|s| s / chunk_size);
+        if end_index.is_some_and(|end_index| start_index >= end_index) {
+            return None;
+        }
+        Some(Box::pin(
+            stream_vecs_from_with_options(
+                self.client.clone(),
+                self.cursor.bucket.clone(),
+                progress.key.clone(),
+                start_index,
+                end_index,
+                chunk_size,
+                self.options.clone(),
+            )
+            .await,
+        ))
+    }
+
+    /// The next chunk, or `None` once every key is read completely.
+    ///
+    /// After an error the session can be continued, which will reopen the
+    /// failed key from where it stopped.
+    pub async fn next(&mut self) -> Option<Result<SessionChunk, VecStreamError>> {
+        loop {
+            let (key_index, mut stream) = match self.current.take() {
+                Some(current) => current,
+                None => {
+                    let key_index = self.cursor.keys.iter().position(|k| !k.done)?;
+                    match self.open(key_index).await {
+                        Some(stream) => (key_index, stream),
+                        None => {
+                            self.cursor.keys[key_index].done = true;
+                            continue;
+                        }
+                    }
+                }
+            };
+
+            match stream.next().await {
+                Some(Ok(data)) => {
+                    let progress = &mut self.cursor.keys[key_index];
+                    let index = progress.bytes_read / self.cursor.chunk_size;
+                    progress.bytes_read += data.len();
+                    if progress.size == Some(progress.bytes_read) {
+                        progress.done = true;
+                    } else {
+                        self.current = Some((key_index, stream));
+                    }
+
+                    return Some(Ok(SessionChunk {
+                        key_index,
+                        index,
+                        data,
+                    }));
+                }
+                Some(Err(e)) => return Some(Err(e)),
+                None => {
+                    self.cursor.keys[key_index].done = true;
+                }
+            }
+        }
+    }
+}
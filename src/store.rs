@@ -0,0 +1,666 @@
+use std::{collections::HashMap, sync::Arc};
+
+use aws_sdk_s3::{
+    error::SdkError,
+    operation::{
+        copy_object::CopyObjectError, delete_object::DeleteObjectError,
+        head_object::HeadObjectOutput, put_object::PutObjectError,
+    },
+    primitives::DateTime,
+    Client,
+};
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use thiserror::Error;
+
+use crate::{
+    audit::{AuditOperation, Auditor},
+    compress::{self, CompressionError, Dictionary, DictionaryStore},
+    download::{self, AsOfError, DownloadVecError, HeadError, VecStreamError},
+    gc::{self, GcError, GcOptions, GcReport},
+    keymap::{KeyForgetError, KeyLookupError, KeyMapping, KeyRecordError},
+    manifest::Manifest,
+    names::{self, BucketName, NameError, ObjectKey},
+    options::OpOptions,
+    quota::{Quota, QuotaExceeded},
+    upload::{Upload, UploadCreateError, Uploads, DEFAULT_SIZE_PER_UPLOAD},
+};
+
+#[derive(Debug, Error)]
+pub enum StorePutError {
+    #[error(transparent)]
+    InvalidKey(#[from] NameError),
+    #[error(transparent)]
+    QuotaExceeded(#[from] QuotaExceeded),
+    #[error("put object failed: {0}")]
+    PutFailed(#[from] SdkError<PutObjectError>),
+    #[error(transparent)]
+    KeyRecordFailed(#[from] KeyRecordError),
+}
+
+#[derive(Debug, Error)]
+pub enum StoreDeleteError {
+    #[error(transparent)]
+    InvalidKey(#[from] NameError),
+    #[error("delete object failed: {0}")]
+    DeleteFailed(#[from] SdkError<DeleteObjectError>),
+    #[error(transparent)]
+    KeyForgetFailed(#[from] KeyForgetError),
+}
+
+#[derive(Debug, Error)]
+pub enum StoreCopyError {
+    #[error(transparent)]
+    InvalidKey(#[from] NameError),
+    #[error(transparent)]
+    QuotaExceeded(#[from] QuotaExceeded),
+    #[error("copy object failed: {0}")]
+    CopyFailed(#[from] SdkError<CopyObjectError>),
+    #[error(transparent)]
+    KeyRecordFailed(#[from] KeyRecordError),
+}
+
+/// A client bound to a single bucket.
+#[derive(Clone)]
+pub struct S3Store {
+    client: Arc<Client>,
+    bucket: BucketName,
+    auditor: Option<Auditor>,
+    quota: Option<Arc<Quota>>,
+    key_mapping: Option<KeyMapping>,
+    options: OpOptions,
+}
+
+impl S3Store {
+    pub fn new(client: Arc<Client>, bucket: BucketName) -> Self {
+        Self {
+            client,
+            bucket,
+            auditor: None,
+            quota: None,
+            key_mapping: None,
+            options: OpOptions::default(),
+        }
+    }
+
+    /// Like `new`, validating the bucket name.
+    pub fn try_new(
+        client: Arc<Client>,
+        bucket: impl TryInto<BucketName, Error = NameError>,
+    ) -> Result<Self, NameError> {
+        Ok(Self::new(client, bucket.try_into()?))
+    }
+
+    /// Record every mutating operation made through this store, including
+    /// those on uploads it creates.
+    pub fn with_auditor(mut self, auditor: Auditor) -> Self {
+        self.auditor = Some(auditor);
+        self
+    }
+
+    /// Reject writes through this store, including those on uploads it
+    /// creates, that would exceed the quota.
+    pub fn with_quota(mut self, quota: Arc<Quota>) -> Self {
+        self.quota = Some(quota);
+        self
+    }
+
+    /// Store every key under its mapped name. Keys passed to the store are
+    /// always the unmapped ones; audit events and quota prefixes refer to
+    /// the mapped ones, as those are what ends up in the bucket.
+    pub fn with_key_mapping(mut self, key_mapping: KeyMapping) -> Self {
+        self.key_mapping = Some(key_mapping);
+        self
+    }
+
+    /// A copy of this store whose operations use `overrides` over the
+    /// settings of this one.
+    pub fn with_op_options(&self, overrides: &OpOptions) -> Self {
+        let mut store = self.clone();
+        store.options = self.options.merge(overrides);
+        store
+    }
+
+    pub fn client(&self) -> &Arc<Client> {
+        &self.client
+    }
+
+    pub fn bucket(&self) -> &str {
+        &self.bucket
+    }
+
+    pub fn auditor(&self) -> Option<&Auditor> {
+        self.auditor.as_ref()
+    }
+
+    pub fn quota(&self) -> Option<&Arc<Quota>> {
+        self.quota.as_ref()
+    }
+
+    pub fn key_mapping(&self) -> Option<&KeyMapping> {
+        self.key_mapping.as_ref()
+    }
+
+    /// The key `key` is stored under.
+    pub fn stored_key(&self, key: &str) -> String {
+        match self.key_mapping.as_ref() {
+            Some(key_mapping) => key_mapping.map_key(key),
+            None => key.to_string(),
+        }
+    }
+
+    /// Validate `key` and turn it into the key it is stored under, which
+    /// has to be valid as well since mapping can make keys longer.
+    fn object_key(&self, key: &str) -> Result<String, NameError> {
+        ObjectKey::validate(key)?;
+        let stored_key = self.stored_key(key);
+        ObjectKey::validate(&stored_key)?;
+
+        Ok(stored_key)
+    }
+
+    /// Look up the original key of a stored key in the key mapping sidecar.
+    pub async fn original_key(&self, stored_key: &str) -> Result<Option<String>, KeyLookupError> {
+        match self.key_mapping.as_ref() {
+            Some(key_mapping) => {
+                key_mapping
+                    .lookup(&self.client, &self.bucket, stored_key)
+                    .await
+            }
+            None => Ok(Some(stored_key.to_string())),
+        }
+    }
+
+    /// Record in the key mapping sidecar that `key` was written as
+    /// `stored_key`.
+    async fn record_key(&self, key: &str, stored_key: &str) -> Result<(), KeyRecordError> {
+        let Some(key_mapping) = self.key_mapping.as_ref() else {
+            return Ok(());
+        };
+        key_mapping
+            .record(
+                &self.client,
+                &self.bucket,
+                key,
+                stored_key,
+                self.auditor.as_ref(),
+                self.quota.as_deref(),
+            )
+            .await
+    }
+
+    /// Remove `stored_key` from the key mapping sidecar.
+    async fn forget_key(&self, stored_key: &str) -> Result<(), KeyForgetError> {
+        let Some(key_mapping) = self.key_mapping.as_ref() else {
+            return Ok(());
+        };
+        key_mapping
+            .forget(
+                &self.client,
+                &self.bucket,
+                stored_key,
+                self.auditor.as_ref(),
+                self.quota.as_deref(),
+            )
+            .await
+    }
+
+    /// The size of the object currently stored under `key`, if a quota
+    /// needs to know it.
+    async fn charged_size(&self, key: &str) -> Option<usize> {
+        self.quota.as_ref()?;
+        let head = download::try_head_with_options(&self.client, &self.bucket, key, &self.options)
+            .await
+            .ok()??;
+
+        head.content_length.map(|len| len as usize)
+    }
+
+    /// Give back the bytes of an object that no longer exists.
+    fn release(&self, key: &str, size: Option<usize>) {
+        if let (Some(quota), Some(size)) = (self.quota.as_ref(), size) {
+            quota.release(key, size);
+        }
+    }
+
+    async fn audit<T, E: std::fmt::Display>(
+        &self,
+        operation: AuditOperation,
+        result: &Result<T, E>,
+    ) {
+        if let Some(auditor) = self.auditor.as_ref() {
+            auditor.record(&self.bucket, operation, result).await;
+        }
+    }
+
+    pub async fn download_vec<T: Copy + Default>(
+        &self,
+        key: &str,
+    ) -> Result<Vec<T>, DownloadVecError> {
+        download::download_vec_with_options(
+            &self.client,
+            &self.bucket,
+            &self.object_key(key)?,
+            &self.options,
+        )
+        .await
+    }
+
+    pub async fn try_download_vec<T: Copy + Default>(
+        &self,
+        key: &str,
+    ) -> Result<Option<Vec<T>>, DownloadVecError> {
+        download::try_download_vec_with_options(
+            &self.client,
+            &self.bucket,
+            &self.object_key(key)?,
+            &self.options,
+        )
+        .await
+    }
+
+    pub async fn head(&self, key: &str) -> Result<HeadObjectOutput, HeadError> {
+        download::head_with_options(
+            &self.client,
+            &self.bucket,
+            &self.object_key(key)?,
+            &self.options,
+        )
+        .await
+    }
+
+    pub async fn try_head(&self, key: &str) -> Result<Option<HeadObjectOutput>, HeadError> {
+        download::try_head_with_options(
+            &self.client,
+            &self.bucket,
+            &self.object_key(key)?,
+            &self.options,
+        )
+        .await
+    }
+
+    pub async fn stream_vecs_from(
+        &self,
+        key: impl Into<String>,
+        start_index: usize,
+        end_index: Option<usize>,
+        chunk_size: usize,
+    ) -> impl Stream<Item = Result<Bytes, VecStreamError>> {
+        let key = match self.object_key(&key.into()) {
+            Ok(key) => key,
+            Err(e) => return futures::stream::once(async { Err(e.into()) }).left_stream(),
+        };
+        download::stream_vecs_from_with_options(
+            self.client.clone(),
+            self.bucket.to_string(),
+            key,
+            start_index,
+            end_index,
+            chunk_size,
+            self.options.clone(),
+        )
+        .await
+        .right_stream()
+    }
+
+    pub async fn try_stream_vecs_from(
+        &self,
+        key: impl Into<String>,
+        start_index: usize,
+        end_index: Option<usize>,
+        chunk_size: usize,
+    ) -> Result<Option<impl Stream<Item = Result<Bytes, VecStreamError>>>, VecStreamError> {
+        download::try_stream_vecs_from_with_options(
+            self.client.clone(),
+            self.bucket.to_string(),
+            self.object_key(&key.into())?,
+            start_index,
+            end_index,
+            chunk_size,
+            self.options.clone(),
+        )
+        .await
+    }
+
+    pub async fn concurrent_stream_vecs_from(
+        &self,
+        key: impl Into<String>,
+        start_index: usize,
+        end_index: Option<usize>,
+        chunk_size: usize,
+    ) -> impl Stream<Item = Result<Bytes, VecStreamError>> {
+        let key = match self.object_key(&key.into()) {
+            Ok(key) => key,
+            Err(e) => return futures::stream::once(async { Err(e.into()) }).left_stream(),
+        };
+        download::concurrent_stream_vecs_from_with_options(
+            self.client.clone(),
+            self.bucket.to_string(),
+            key,
+            start_index,
+            end_index,
+            chunk_size,
+            self.options.clone(),
+        )
+        .await
+        .right_stream()
+    }
+
+    pub async fn stream_vecs_as_of(
+        &self,
+        key: impl Into<String>,
+        timestamp: DateTime,
+        chunk_size: usize,
+    ) -> Result<impl Stream<Item = Result<Bytes, VecStreamError>>, AsOfError> {
+        download::stream_vecs_as_of_with_options(
+            self.client.clone(),
+            self.bucket.to_string(),
+            self.object_key(&key.into())?,
+            timestamp,
+            chunk_size,
+            self.options.clone(),
+        )
+        .await
+    }
+
+    pub async fn put_object(
+        &self,
+        key: impl Into<String>,
+        data: Bytes,
+    ) -> Result<(), StorePutError> {
+        self.put(key.into(), data, None).await
+    }
+
+    /// Compress `data` and store it, recording the dictionary used (if any)
+    /// in the object metadata.
+    pub async fn put_compressed(
+        &self,
+        key: impl Into<String>,
+        data: &[u8],
+        dictionary: Option<&Dictionary>,
+        level: i32,
+    ) -> Result<(), CompressionError> {
+        let compressed = compress::compress(data, dictionary, level)?;
+        self.put(
+            key.into(),
+            compressed.into(),
+            compress::dictionary_metadata(dictionary),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Fetch and decompress an object written by `put_compressed`.
+    pub async fn get_decompressed(
+        &self,
+        key: &str,
+        dictionaries: &DictionaryStore,
+    ) -> Result<Vec<u8>, CompressionError> {
+        compress::get_decompressed_with_options(
+            &self.client,
+            &self.bucket,
+            &self.object_key(key)?,
+            dictionaries,
+            &self.options,
+        )
+        .await
+    }
+
+    /// Dictionaries stored in this store under `prefix`.
+    pub fn dictionaries(&self, prefix: impl Into<String>) -> DictionaryStore {
+        DictionaryStore::new(self.clone(), prefix.into())
+    }
+
+    async fn put(
+        &self,
+        original_key: String,
+        data: Bytes,
+        metadata: Option<HashMap<String, String>>,
+    ) -> Result<(), StorePutError> {
+        let key = self.object_key(&original_key)?;
+        let size = data.len();
+        if let Some(quota) = self.quota.as_ref() {
+            quota.charge(&key, size)?;
+        }
+        let replaced = self.charged_size(&key).await;
+        let result = self
+            .client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .set_metadata(metadata)
+            .body(data.into())
+            .customize()
+            .config_override(self.options.config_override())
+            .send()
+            .await;
+        match result {
+            Ok(_) => self.release(&key, replaced),
+            Err(_) => self.release(&key, Some(size)),
+        }
+        let operation = AuditOperation::Put {
+            key: key.clone(),
+            size,
+        };
+        self.audit(operation, &result).await;
+        result?;
+        self.record_key(&original_key, &key).await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_object(&self, key: impl Into<String>) -> Result<(), StoreDeleteError> {
+        let key = self.object_key(&key.into())?;
+        let size = self.charged_size(&key).await;
+        let result = self
+            .client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .customize()
+            .config_override(self.options.config_override())
+            .send()
+            .await;
+        if result.is_ok() {
+            self.release(&key, size);
+        }
+        self.audit(AuditOperation::Delete { key: key.clone() }, &result)
+            .await;
+        result?;
+        self.forget_key(&key).await?;
+
+        Ok(())
+    }
+
+    /// Copy an object within this store's bucket.
+    pub async fn copy_object(
+        &self,
+        source_key: impl Into<String>,
+        key: impl Into<String>,
+    ) -> Result<(), StoreCopyError> {
+        let source_key = self.object_key(&source_key.into())?;
+        let original_key = key.into();
+        let key = self.object_key(&original_key)?;
+        let size = self.charged_size(&source_key).await;
+        if let (Some(quota), Some(size)) = (self.quota.as_ref(), size) {
+            quota.charge(&key, size)?;
+        }
+        let replaced = self.charged_size(&key).await;
+        let result = self
+            .client
+            .copy_object()
+            .bucket(&self.bucket)
+            .copy_source(names::copy_source(&self.bucket, &source_key))
+            .key(&key)
+            .customize()
+            .config_override(self.options.config_override())
+            .send()
+            .await;
+        match result {
+            Ok(_) => self.release(&key, replaced),
+            Err(_) => self.release(&key, size),
+        }
+        let operation = AuditOperation::Copy {
+            source_bucket: self.bucket.to_string(),
+            source_key,
+            key: key.clone(),
+        };
+        self.audit(operation, &result).await;
+        result?;
+        self.record_key(&original_key, &key).await?;
+
+        Ok(())
+    }
+
+    /// Delete everything under `prefix` not referenced by `manifests`.
+    ///
+    /// With a key mapping, the prefix should end on a `/`, as only whole
+    /// segments map to a prefix of the stored keys. The keys in the report
+    /// are stored keys. Sidecar entries of deleted keys are removed too; any
+    /// that could not be are listed under `failed`.
+    pub async fn gc_prefix(
+        &self,
+        prefix: &str,
+        manifests: &[Manifest],
+        mut options: GcOptions,
+    ) -> Result<GcReport, GcError> {
+        let referenced = manifests
+            .iter()
+            .flat_map(|m| m.keys())
+            .map(|key| self.stored_key(key))
+            .collect();
+        options.keep = options
+            .keep
+            .iter()
+            .map(|key| self.stored_key(key))
+            .collect();
+        let mut report = gc::gc_unreferenced(
+            &self.client,
+            &self.bucket,
+            &self.stored_key(prefix),
+            &referenced,
+            options,
+        )
+        .await?;
+
+        if !report.dry_run {
+            for key in report.deleted.iter() {
+                self.release(key, report.sizes.get(key).copied());
+                let operation = AuditOperation::Delete { key: key.clone() };
+                self.audit(operation, &Ok::<(), String>(())).await;
+            }
+            let mut forget_failed = Vec::new();
+            for key in report.deleted.iter() {
+                if let Err(error) = self.forget_key(key).await {
+                    forget_failed.push((key.clone(), error.to_string()));
+                }
+            }
+            for (key, error) in report.failed.iter() {
+                let operation = AuditOperation::Delete { key: key.clone() };
+                self.audit(operation, &Err::<(), _>(error)).await;
+            }
+            report.failed.extend(forget_failed);
+        }
+
+        Ok(report)
+    }
+
+    async fn prepare_upload(
+        &self,
+        original_key: String,
+        key: String,
+        result: Result<Upload, UploadCreateError>,
+    ) -> Result<Upload, UploadCreateError> {
+        let result = match (result, self.key_mapping.as_ref()) {
+            (Ok(upload), Some(key_mapping)) => {
+                Ok(upload.with_key_record(key_mapping.clone(), original_key))
+            }
+            (result, _) => result,
+        };
+        let mut result = match (result, self.quota.as_ref()) {
+            (Ok(upload), Some(quota)) => {
+                let replaced = self.charged_size(&key).await.unwrap_or(0);
+                Ok(upload.with_quota(quota.clone()).replacing(replaced))
+            }
+            (result, _) => result,
+        };
+        if let Some(auditor) = self.auditor.as_ref() {
+            let operation = AuditOperation::CreateMultipartUpload {
+                key,
+                upload_id: result
+                    .as_ref()
+                    .ok()
+                    .map(|upload| upload.info.upload_id().to_string()),
+            };
+            auditor.record(&self.bucket, operation, &result).await;
+            result = result.map(|upload| upload.with_auditor(auditor.clone()));
+        }
+
+        result
+    }
+
+    pub async fn upload(&self, key: impl Into<String>) -> Result<Upload, UploadCreateError> {
+        let original_key = key.into();
+        let key = self.object_key(&original_key)?;
+        let result = Upload::new_with_options(
+            self.client.clone(),
+            self.bucket.to_string(),
+            key.clone(),
+            DEFAULT_SIZE_PER_UPLOAD,
+            self.options.clone(),
+        )
+        .await;
+        self.prepare_upload(original_key, key, result).await
+    }
+
+    pub async fn upload_with_size(
+        &self,
+        key: impl Into<String>,
+        size_per_upload: usize,
+    ) -> Result<Upload, UploadCreateError> {
+        let original_key = key.into();
+        let key = self.object_key(&original_key)?;
+        let result = Upload::new_with_options(
+            self.client.clone(),
+            self.bucket.to_string(),
+            key.clone(),
+            size_per_upload,
+            self.options.clone(),
+        )
+        .await;
+        self.prepare_upload(original_key, key, result).await
+    }
+
+    pub async fn uploads(
+        &self,
+        prefix: impl Into<String>,
+        amount: usize,
+    ) -> Result<Uploads, UploadCreateError> {
+        let prefix = prefix.into();
+        let mut uploads = Vec::with_capacity(amount);
+        for index in 0..amount {
+            uploads.push(self.upload(format!("{prefix}{index}")).await?);
+        }
+
+        Ok(Uploads::from_uploads(uploads))
+    }
+
+    pub async fn uploads_with_size(
+        &self,
+        prefix: impl Into<String>,
+        amount: usize,
+        size_per_upload: usize,
+    ) -> Result<Uploads, UploadCreateError> {
+        let prefix = prefix.into();
+        let mut uploads = Vec::with_capacity(amount);
+        for index in 0..amount {
+            uploads.push(
+                self.upload_with_size(format!("{prefix}{index}"), size_per_upload)
+                    .await?,
+            );
+        }
+
+        Ok(Uploads::from_uploads(uploads))
+    }
+}
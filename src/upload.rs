@@ -1,87 +1,673 @@
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, SystemTime};
 
 use aws_sdk_s3::{
-    error::SdkError,
+    error::{ProvideErrorMetadata, SdkError},
     operation::{
-        complete_multipart_upload::CompleteMultipartUploadError,
-        create_multipart_upload::CreateMultipartUploadError, upload_part::UploadPartError,
+        abort_multipart_upload::AbortMultipartUploadError,
+        complete_multipart_upload::{CompleteMultipartUploadError, CompleteMultipartUploadOutput},
+        create_multipart_upload::CreateMultipartUploadError,
+        list_multipart_uploads::ListMultipartUploadsError,
+        upload_part::{UploadPartError, UploadPartOutput},
+        upload_part_copy::UploadPartCopyError,
     },
-    types::{CompletedMultipartUpload, CompletedPart},
+    types::{CompletedMultipartUpload, CompletedPart, CopyPartResult},
     Client,
 };
 use bytes::{Bytes, BytesMut};
-use serde::{Deserialize, Serialize};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
 use thiserror::Error;
 use tokio::{sync::Mutex, task::JoinHandle};
 
+use crate::{
+    audit::{AuditOperation, Auditor},
+    keymap::{KeyMapping, KeyRecordError},
+    migrate::LegacyUploadInfo,
+    names::{self, NameError, ObjectKey},
+    options::{OpOptions, Throttle},
+    pool::{BufferPool, Pooled},
+    quota::{Quota, QuotaExceeded},
+};
+
+pub const DEFAULT_SIZE_PER_UPLOAD: usize = 512 << 20;
+
 struct UploadResult {
     bytes_sent: usize,
-    e_tag: String,
+    part_id: PartId,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChecksumAlgorithm {
+    Crc32,
+    Crc32C,
+    Crc64Nvme,
+    Sha1,
+    Sha256,
 }
 
+/// What identifies an uploaded part when completing the upload.
+///
+/// This is normally the ETag, but some S3-compatible stores only return a
+/// checksum in checksum mode.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PartId {
+    ETag(String),
+    Checksum {
+        algorithm: ChecksumAlgorithm,
+        checksum: String,
+    },
+}
+
+impl PartId {
+    fn from_output(output: UploadPartOutput) -> Option<Self> {
+        Self::from_fields(
+            output.e_tag,
+            [
+                (ChecksumAlgorithm::Crc32, output.checksum_crc32),
+                (ChecksumAlgorithm::Crc32C, output.checksum_crc32_c),
+                (ChecksumAlgorithm::Crc64Nvme, output.checksum_crc64_nvme),
+                (ChecksumAlgorithm::Sha1, output.checksum_sha1),
+                (ChecksumAlgorithm::Sha256, output.checksum_sha256),
+            ],
+        )
+    }
+
+    fn from_copy_result(result: CopyPartResult) -> Option<Self> {
+        Self::from_fields(
+            result.e_tag,
+            [
+                (ChecksumAlgorithm::Crc32, result.checksum_crc32),
+                (ChecksumAlgorithm::Crc32C, result.checksum_crc32_c),
+                (ChecksumAlgorithm::Crc64Nvme, result.checksum_crc64_nvme),
+                (ChecksumAlgorithm::Sha1, result.checksum_sha1),
+                (ChecksumAlgorithm::Sha256, result.checksum_sha256),
+            ],
+        )
+    }
+
+    fn from_fields(
+        e_tag: Option<String>,
+        checksums: [(ChecksumAlgorithm, Option<String>); 5],
+    ) -> Option<Self> {
+        if let Some(e_tag) = e_tag {
+            return Some(Self::ETag(e_tag));
+        }
+
+        checksums.into_iter().find_map(|(algorithm, checksum)| {
+            checksum.map(|checksum| Self::Checksum {
+                algorithm,
+                checksum,
+            })
+        })
+    }
+
+    fn completed_part(self, part_number: i32) -> CompletedPart {
+        let part = CompletedPart::builder().part_number(part_number);
+        let part = match self {
+            Self::ETag(e_tag) => part.e_tag(e_tag),
+            Self::Checksum {
+                algorithm,
+                checksum,
+            } => match algorithm {
+                ChecksumAlgorithm::Crc32 => part.checksum_crc32(checksum),
+                ChecksumAlgorithm::Crc32C => part.checksum_crc32_c(checksum),
+                ChecksumAlgorithm::Crc64Nvme => part.checksum_crc64_nvme(checksum),
+                ChecksumAlgorithm::Sha1 => part.checksum_sha1(checksum),
+                ChecksumAlgorithm::Sha256 => part.checksum_sha256(checksum),
+            },
+        };
+
+        part.build()
+    }
+}
+
+/// A multipart upload of a single object.
+///
+/// Like the sdk client it wraps, an upload needs a tokio runtime:
+/// `new`, `complete` and `abort` return plain futures which can be polled
+/// from any executor, but only within the context of a tokio runtime, for
+/// instance through `Runtime::enter`. The same goes for the poll-based
+/// `poll_send_ready` and `poll_part_complete`.
 pub struct Upload {
     client: Arc<Client>,
     pub info: UploadInfo,
     data: BytesMut,
-    upload_task: Option<JoinHandle<Result<UploadResult, SdkError<UploadPartError>>>>,
+    upload_task: Option<JoinHandle<Result<UploadResult, UploadSendError>>>,
+    refresh_task: Option<JoinHandle<(UploadInfo, Result<(), UploadSendError>)>>,
+    auditor: Option<Auditor>,
+    quota: Option<Arc<Quota>>,
+    options: OpOptions,
+    throttle: Option<Throttle>,
+    /// The size of the part currently being uploaded.
+    in_flight_bytes: usize,
+    /// The size of the object this upload overwrites, given back to the
+    /// quota once it completes.
+    replaced_bytes: usize,
+    /// The mapping and original key to record once the upload completes.
+    key_record: Option<(KeyMapping, String)>,
+    max_session_duration: Option<Duration>,
+    /// `size()` as of the last change, readable without access to the
+    /// upload itself.
+    published_size: Arc<std::sync::Mutex<ShardSize>>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Deserializing accepts info written by any earlier version of this crate,
+/// upgrading it on the way. Versions are told apart by their shape, so this
+/// needs a self-describing format such as JSON or CBOR.
+#[derive(Clone, Debug, Serialize)]
+#[serde(into = "UploadInfoV1")]
 pub struct UploadInfo {
     bucket: String,
     key: String,
     size_per_upload: usize,
 
     upload_id: String,
-    parts: Vec<String>,
+    parts: Vec<PartId>,
+    pub uploaded_bytes: usize,
+    /// When the multipart upload was created, if known.
+    created_at: Option<SystemTime>,
+    /// Set while a refresh has completed the upload session but not yet
+    /// copied it into a new one.
+    completed: Option<CompletedSession>,
+}
+
+/// An upload session completed by a refresh, whose object still has to be
+/// copied into a new session.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompletedSession {
+    /// The etag of the completed object, if the store returned one.
+    pub e_tag: Option<String>,
+}
+
+/// Version 1 of how `UploadInfo` is serialized. This format is stable.
+///
+/// As JSON, this is an object with `"version": 1` and the fields below.
+/// Each part is either an etag string or an object with an `algorithm`
+/// (`crc32`, `crc32_c`, `crc64_nvme`, `sha1` or `sha256`) and a
+/// `checksum`. `created_at` is an object with `secs_since_epoch` and
+/// `nanos_since_epoch`, or null. `completed` is only present while a
+/// refresh is halfway done, as an object with the `e_tag` of the completed
+/// object.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadInfoV1 {
+    pub version: u32,
+    pub bucket: String,
+    pub key: String,
+    pub size_per_upload: usize,
+    pub upload_id: String,
+    pub parts: Vec<PartId>,
     pub uploaded_bytes: usize,
+    pub created_at: Option<SystemTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completed: Option<CompletedSession>,
+}
+
+#[derive(Debug, Error)]
+#[error("unsupported upload info version {0}")]
+pub struct UnsupportedVersion(pub u32);
+
+impl From<UploadInfo> for UploadInfoV1 {
+    fn from(info: UploadInfo) -> Self {
+        Self {
+            version: 1,
+            bucket: info.bucket,
+            key: info.key,
+            size_per_upload: info.size_per_upload,
+            upload_id: info.upload_id,
+            parts: info.parts,
+            uploaded_bytes: info.uploaded_bytes,
+            created_at: info.created_at,
+            completed: info.completed,
+        }
+    }
+}
+
+impl TryFrom<UploadInfoV1> for UploadInfo {
+    type Error = UnsupportedVersion;
+
+    fn try_from(info: UploadInfoV1) -> Result<Self, UnsupportedVersion> {
+        if info.version != 1 {
+            return Err(UnsupportedVersion(info.version));
+        }
+
+        Ok(Self {
+            bucket: info.bucket,
+            key: info.key,
+            size_per_upload: info.size_per_upload,
+            upload_id: info.upload_id,
+            parts: info.parts,
+            uploaded_bytes: info.uploaded_bytes,
+            created_at: info.created_at,
+            completed: info.completed,
+        })
+    }
+}
+
+/// Any format `UploadInfo` was ever written in. Versioned info is tried
+/// first, as legacy info lacks fields it requires.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AnyUploadInfo {
+    V1(UploadInfoV1),
+    Legacy(LegacyUploadInfo),
+}
+
+impl<'de> Deserialize<'de> for UploadInfo {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let info = match AnyUploadInfo::deserialize(deserializer)? {
+            AnyUploadInfo::V1(info) => info,
+            AnyUploadInfo::Legacy(legacy) => legacy.into(),
+        };
+
+        info.try_into().map_err(D::Error::custom)
+    }
+}
+
+impl UploadInfo {
+    pub fn bucket(&self) -> &str {
+        &self.bucket
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn upload_id(&self) -> &str {
+        &self.upload_id
+    }
+
+    fn expired(&self) -> UploadExpired {
+        UploadExpired {
+            key: self.key.clone(),
+            upload_id: self.upload_id.clone(),
+        }
+    }
+
+    /// How long ago the multipart upload was created, if known.
+    pub fn age(&self) -> Option<Duration> {
+        self.created_at?.elapsed().ok()
+    }
+
+    /// Whether the session has to be refreshed before uploading more.
+    fn expiring(&self, max_session_duration: Option<Duration>) -> bool {
+        if self.completed.is_some() {
+            return true;
+        }
+        match (max_session_duration, self.age()) {
+            (Some(max), Some(age)) => age >= max,
+            _ => false,
+        }
+    }
+
+    /// Whether a refresh might be due, counting sessions of unknown age.
+    fn refresh_due(&self, max_session_duration: Option<Duration>) -> bool {
+        self.expiring(max_session_duration)
+            || (max_session_duration.is_some() && self.created_at.is_none())
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("upload {upload_id} of {key} no longer exists, it was likely aborted by a lifecycle rule")]
+pub struct UploadExpired {
+    pub key: String,
+    pub upload_id: String,
+}
+
+fn is_no_such_upload<E: ProvideErrorMetadata>(error: &SdkError<E>) -> bool {
+    error.code() == Some("NoSuchUpload")
+}
+
+fn part_failed(error: SdkError<UploadPartError>, key: &str, upload_id: &str) -> UploadSendError {
+    if is_no_such_upload(&error) {
+        UploadSendError::Expired(UploadExpired {
+            key: key.to_string(),
+            upload_id: upload_id.to_string(),
+        })
+    } else {
+        error.into()
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum UploadCreateError {
+    #[error(transparent)]
+    InvalidKey(#[from] NameError),
+    #[error("create multipart upload failed: {0}")]
+    CreateFailed(#[from] SdkError<CreateMultipartUploadError>),
+}
+
+#[derive(Debug, Error)]
+pub enum UploadSendError {
+    #[error("part upload failed: {0}")]
+    PartFailed(#[from] SdkError<UploadPartError>),
+    #[error("upload of part {0} returned neither an etag nor a checksum")]
+    NoPartId(i32),
+    #[error(transparent)]
+    QuotaExceeded(#[from] QuotaExceeded),
+    #[error(transparent)]
+    Expired(#[from] UploadExpired),
+    #[error("completing the expiring upload session failed: {0}")]
+    RefreshCompletionFailed(SdkError<CompleteMultipartUploadError>),
+    #[error("creating a new upload session failed: {0}")]
+    RefreshCreateFailed(SdkError<CreateMultipartUploadError>),
+    #[error("copying uploaded data into the new upload session failed: {0}")]
+    RefreshCopyFailed(SdkError<UploadPartCopyError>),
+    #[error("looking up when the upload session was created failed: {0}")]
+    RefreshLookupFailed(SdkError<ListMultipartUploadsError>),
+    #[error("parts can only be uploaded within a tokio runtime")]
+    NoRuntime,
+    #[error("the upload session needs a refresh, poll send readiness first")]
+    RefreshPending,
 }
 
 #[derive(Debug, Error)]
 pub enum UploadCompleteError {
     #[error("final part upload failed: {0}")]
-    FinalPartFailed(SdkError<UploadPartError>),
+    FinalPartFailed(UploadSendError),
     #[error("complete multipart upload failed: {0}")]
     CompletionFailed(SdkError<CompleteMultipartUploadError>),
+    #[error(transparent)]
+    Expired(#[from] UploadExpired),
+    #[error(transparent)]
+    KeyRecordFailed(#[from] KeyRecordError),
+}
+
+/// What a session refresh works on, taken out of an `Upload` so that it
+/// can run as a task of its own.
+struct SessionRefresh {
+    client: Arc<Client>,
+    info: UploadInfo,
+    options: OpOptions,
+    auditor: Option<Auditor>,
+    max_session_duration: Option<Duration>,
+}
+
+impl SessionRefresh {
+    /// Refresh, handing back the info of the session to continue with.
+    async fn run(mut self) -> (UploadInfo, Result<(), UploadSendError>) {
+        let result = self.refresh().await;
+        (self.info, result)
+    }
+
+    /// Like `run`, but only refresh if the session is expiring, looking up
+    /// its age first if that isn't known.
+    async fn run_if_expiring(mut self) -> (UploadInfo, Result<(), UploadSendError>) {
+        let result = self.refresh_if_expiring().await;
+        (self.info, result)
+    }
+
+    async fn refresh_if_expiring(&mut self) -> Result<(), UploadSendError> {
+        if self.max_session_duration.is_some() && self.info.created_at.is_none() {
+            self.lookup_created_at().await?;
+        }
+        if self.info.expiring(self.max_session_duration) {
+            self.refresh().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Fill in when the session was created, for info saved before that was
+    /// recorded. A session that isn't listed anymore counts as new, as
+    /// uploading to it will report it expired anyway.
+    async fn lookup_created_at(&mut self) -> Result<(), UploadSendError> {
+        let mut key_marker = None;
+        let mut upload_id_marker = None;
+        let mut created_at = None;
+        loop {
+            let listed = self
+                .client
+                .list_multipart_uploads()
+                .bucket(&self.info.bucket)
+                .prefix(&self.info.key)
+                .set_key_marker(key_marker)
+                .set_upload_id_marker(upload_id_marker)
+                .customize()
+                .config_override(self.options.config_override())
+                .send()
+                .await
+                .map_err(UploadSendError::RefreshLookupFailed)?;
+            let upload = listed.uploads().iter().find(|upload| {
+                upload.key() == Some(self.info.key.as_str())
+                    && upload.upload_id() == Some(self.info.upload_id.as_str())
+            });
+            if let Some(upload) = upload {
+                created_at = upload
+                    .initiated()
+                    .and_then(|initiated| SystemTime::try_from(*initiated).ok());
+                break;
+            }
+            if !listed.is_truncated().unwrap_or(false) {
+                break;
+            }
+            key_marker = listed.next_key_marker;
+            upload_id_marker = listed.next_upload_id_marker;
+        }
+        self.info.created_at = Some(created_at.unwrap_or_else(SystemTime::now));
+
+        Ok(())
+    }
+
+    /// Abort a session this refresh created but couldn't use.
+    async fn abort_session(&self, upload_id: &str) {
+        // best effort, an unused session is cleaned up by lifecycle rules too
+        let _ = self
+            .client
+            .abort_multipart_upload()
+            .bucket(&self.info.bucket)
+            .key(&self.info.key)
+            .upload_id(upload_id)
+            .customize()
+            .config_override(self.options.config_override())
+            .send()
+            .await;
+    }
+
+    async fn refresh(&mut self) -> Result<(), UploadSendError> {
+        let new_upload = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.info.bucket)
+            .key(&self.info.key)
+            .customize()
+            .config_override(self.options.config_override())
+            .send()
+            .await;
+        if let Some(auditor) = self.auditor.as_ref() {
+            let operation = AuditOperation::CreateMultipartUpload {
+                key: self.info.key.clone(),
+                upload_id: new_upload.as_ref().ok().and_then(|u| u.upload_id.clone()),
+            };
+            auditor
+                .record(&self.info.bucket, operation, &new_upload)
+                .await;
+        }
+        let new_upload_id = new_upload
+            .map_err(UploadSendError::RefreshCreateFailed)?
+            .upload_id
+            .unwrap();
+
+        if self.info.parts.is_empty() && self.info.completed.is_none() {
+            let old_upload_id = std::mem::replace(&mut self.info.upload_id, new_upload_id);
+            // nothing to keep, and an expired upload can't be aborted anyway
+            self.abort_session(&old_upload_id).await;
+            self.info.created_at = Some(SystemTime::now());
+            return Ok(());
+        }
+
+        // once completed, the session stays marked as such until its object
+        // is copied, so that a failed copy can be retried
+        let e_tag = match self.info.completed.as_ref() {
+            Some(completed) => completed.e_tag.clone(),
+            None => {
+                let completed = complete_session(
+                    &self.client,
+                    &self.info,
+                    &self.options,
+                    self.auditor.as_ref(),
+                )
+                .await;
+                let completed = match completed {
+                    Ok(completed) => completed,
+                    Err(e) => {
+                        self.abort_session(&new_upload_id).await;
+                        return Err(if is_no_such_upload(&e) {
+                            UploadSendError::Expired(self.info.expired())
+                        } else {
+                            UploadSendError::RefreshCompletionFailed(e)
+                        });
+                    }
+                };
+                self.info.completed = Some(CompletedSession {
+                    e_tag: completed.e_tag.clone(),
+                });
+                completed.e_tag
+            }
+        };
+
+        match self.copy_into(&new_upload_id, e_tag).await {
+            Ok(parts) => {
+                self.info.upload_id = new_upload_id;
+                self.info.parts = parts;
+                self.info.created_at = Some(SystemTime::now());
+                self.info.completed = None;
+
+                Ok(())
+            }
+            Err(e) => {
+                self.abort_session(&new_upload_id).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Copy the completed object into the parts of session `upload_id`.
+    async fn copy_into(
+        &self,
+        upload_id: &str,
+        e_tag: Option<String>,
+    ) -> Result<Vec<PartId>, UploadSendError> {
+        // every part but the last must be at least 5MiB, so the remainder
+        // goes into the last copied part
+        let size = self.info.size_per_upload;
+        let copies = (self.info.uploaded_bytes / size).max(1);
+        let mut parts = Vec::with_capacity(copies);
+        for ix in 0..copies {
+            let start = ix * size;
+            let end = if ix + 1 == copies {
+                self.info.uploaded_bytes
+            } else {
+                start + size
+            };
+            let part_num = (ix + 1) as i32;
+            let copy = self
+                .client
+                .upload_part_copy()
+                .bucket(&self.info.bucket)
+                .key(&self.info.key)
+                .upload_id(upload_id)
+                .part_number(part_num)
+                .copy_source(names::copy_source(&self.info.bucket, &self.info.key))
+                .copy_source_range(format!("bytes={}-{}", start, end - 1))
+                .set_copy_source_if_match(e_tag.clone())
+                .customize()
+                .config_override(self.options.config_override())
+                .send()
+                .await
+                .map_err(UploadSendError::RefreshCopyFailed)?;
+            let part_id = copy
+                .copy_part_result
+                .and_then(PartId::from_copy_result)
+                .ok_or(UploadSendError::NoPartId(part_num))?;
+            parts.push(part_id);
+        }
+
+        Ok(parts)
+    }
+}
+
+async fn complete_session(
+    client: &Client,
+    info: &UploadInfo,
+    options: &OpOptions,
+    auditor: Option<&Auditor>,
+) -> Result<CompleteMultipartUploadOutput, SdkError<CompleteMultipartUploadError>> {
+    let parts: Vec<_> = info
+        .parts
+        .iter()
+        .cloned()
+        .enumerate()
+        .map(|(ix, part_id)| part_id.completed_part((ix + 1) as i32))
+        .collect();
+
+    let result = client
+        .complete_multipart_upload()
+        .bucket(&info.bucket)
+        .key(&info.key)
+        .upload_id(&info.upload_id)
+        .multipart_upload(
+            CompletedMultipartUpload::builder()
+                .set_parts(Some(parts))
+                .build(),
+        )
+        .customize()
+        .config_override(options.config_override())
+        .send()
+        .await;
+    if let Some(auditor) = auditor {
+        let operation = AuditOperation::CompleteMultipartUpload {
+            key: info.key.clone(),
+            upload_id: info.upload_id.clone(),
+            parts: info.parts.len(),
+            uploaded_bytes: info.uploaded_bytes,
+        };
+        auditor.record(&info.bucket, operation, &result).await;
+    }
+
+    result
 }
 
 impl Upload {
     pub fn new_from_info(client: Arc<Client>, info: UploadInfo) -> Upload {
-        Self {
+        let upload = Self {
             client: client.clone(),
             data: BytesMut::new(),
             info,
             upload_task: None,
-        }
+            refresh_task: None,
+            auditor: None,
+            quota: None,
+            options: OpOptions::default(),
+            throttle: None,
+            in_flight_bytes: 0,
+            replaced_bytes: 0,
+            key_record: None,
+            max_session_duration: None,
+            published_size: Arc::default(),
+        };
+        upload.publish_size();
+
+        upload
     }
 
     pub async fn new(
         client: Arc<Client>,
         bucket: String,
         key: String,
-    ) -> Result<Upload, SdkError<CreateMultipartUploadError>> {
-        const SIZE_PER_UPLOAD: usize = 512 << 20;
-        let upload = client
-            .create_multipart_upload()
-            .bucket(&bucket)
-            .key(&key)
-            .send()
-            .await?;
-        let upload = Upload {
-            client: client.clone(),
-            data: BytesMut::new(),
-            info: UploadInfo {
-                bucket,
-                key,
-                upload_id: upload.upload_id.unwrap(),
-                parts: Vec::new(),
-                size_per_upload: SIZE_PER_UPLOAD,
-                uploaded_bytes: 0,
-            },
-            upload_task: None,
-        };
-
-        Ok(upload)
+    ) -> Result<Upload, UploadCreateError> {
+        Self::new_with_options(
+            client,
+            bucket,
+            key,
+            DEFAULT_SIZE_PER_UPLOAD,
+            OpOptions::default(),
+        )
+        .await
     }
 
     pub async fn new_with_size(
@@ -89,44 +675,195 @@ impl Upload {
         bucket: String,
         key: String,
         size_per_upload: usize,
-    ) -> Result<Upload, SdkError<CreateMultipartUploadError>> {
+    ) -> Result<Upload, UploadCreateError> {
+        Self::new_with_options(client, bucket, key, size_per_upload, OpOptions::default()).await
+    }
+
+    pub async fn new_with_options(
+        client: Arc<Client>,
+        bucket: String,
+        key: String,
+        size_per_upload: usize,
+        options: OpOptions,
+    ) -> Result<Upload, UploadCreateError> {
+        ObjectKey::validate(&key)?;
         let upload = client
             .create_multipart_upload()
             .bucket(&bucket)
             .key(&key)
+            .customize()
+            .config_override(options.config_override())
             .send()
             .await?;
-        let upload = Upload {
-            client: client.clone(),
-            data: BytesMut::new(),
-            info: UploadInfo {
+        let upload = Upload::new_from_info(
+            client,
+            UploadInfo {
                 bucket,
                 key,
                 upload_id: upload.upload_id.unwrap(),
                 parts: Vec::new(),
                 size_per_upload,
                 uploaded_bytes: 0,
+                created_at: Some(SystemTime::now()),
+                completed: None,
             },
-            upload_task: None,
-        };
+        )
+        .with_options(options);
 
         Ok(upload)
     }
 
-    async fn start_part_upload(&mut self) -> Result<(), SdkError<UploadPartError>> {
+    /// Record completion and abortion of this upload.
+    pub fn with_auditor(mut self, auditor: Auditor) -> Self {
+        self.auditor = Some(auditor);
+        self
+    }
+
+    /// Charge all data sent to this upload against a quota.
+    pub fn with_quota(mut self, quota: Arc<Quota>) -> Self {
+        self.quota = Some(quota);
+        self
+    }
+
+    /// Give back `bytes` to the quota once complete, for the object this
+    /// upload overwrites.
+    pub(crate) fn replacing(mut self, bytes: usize) -> Self {
+        self.replaced_bytes = bytes;
+        self
+    }
+
+    /// Record in the sidecar of `key_mapping` that `key` is stored as this
+    /// upload's key, once the upload completes.
+    pub(crate) fn with_key_record(mut self, key_mapping: KeyMapping, key: String) -> Self {
+        self.key_record = Some((key_mapping, key));
+        self
+    }
+
+    /// Give back quota for bytes that will never be uploaded.
+    fn release(&self, bytes: usize) {
+        if let Some(quota) = self.quota.as_ref() {
+            quota.release(&self.info.key, bytes);
+        }
+    }
+
+    /// Override the client settings for every request this upload makes.
+    pub fn with_options(mut self, options: OpOptions) -> Self {
+        self.throttle = options.throttle();
+        self.options = options;
+        self
+    }
+
+    /// Refresh the upload session once it is older than `duration`, before
+    /// a bucket lifecycle rule for incomplete uploads can abort it.
+    ///
+    /// See `refresh` for what that involves.
+    pub fn with_max_session_duration(mut self, duration: Duration) -> Self {
+        self.max_session_duration = Some(duration);
+        self
+    }
+
+    fn expired(&self) -> UploadExpired {
+        self.info.expired()
+    }
+
+    /// Replace the multipart upload with a fresh one, keeping everything
+    /// uploaded so far.
+    ///
+    /// The current upload is completed and its object copied into the parts
+    /// of a new upload, so the object is briefly visible with just the
+    /// data uploaded so far.
+    pub async fn refresh(&mut self) -> Result<(), UploadSendError> {
+        self.finish_part_upload().await?;
+        let (info, result) = self.session_refresh().run().await;
+        self.info = info;
+        self.publish_size();
+
+        result
+    }
+
+    /// Refresh if the session is expiring, or might be as its age isn't
+    /// known yet.
+    async fn refresh_if_expiring(&mut self) -> Result<(), UploadSendError> {
+        if !self.info.refresh_due(self.max_session_duration) {
+            return Ok(());
+        }
+        self.finish_part_upload().await?;
+        let (info, result) = self.session_refresh().run_if_expiring().await;
+        self.info = info;
+        self.publish_size();
+
+        result
+    }
+
+    fn session_refresh(&self) -> SessionRefresh {
+        SessionRefresh {
+            client: self.client.clone(),
+            info: self.info.clone(),
+            options: self.options.clone(),
+            auditor: self.auditor.clone(),
+            max_session_duration: self.max_session_duration,
+        }
+    }
+
+    /// Poll a refresh of the session to completion if it is expiring,
+    /// starting one if needed.
+    fn poll_refresh(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), UploadSendError>> {
+        if self.refresh_task.is_none() {
+            if !self.info.refresh_due(self.max_session_duration) {
+                return Poll::Ready(Ok(()));
+            }
+            // the part in flight still belongs to the old session
+            ready!(self.poll_part_complete(cx))?;
+            self.refresh_task = Some(tokio::spawn(self.session_refresh().run_if_expiring()));
+        }
+
+        let refresh_task = self
+            .refresh_task
+            .as_mut()
+            .expect("refresh task was started");
+        let (info, result) =
+            ready!(Pin::new(refresh_task).poll(cx)).expect("join failed on refresh task");
+        self.refresh_task = None;
+        self.info = info;
+        self.publish_size();
+
+        Poll::Ready(result)
+    }
+
+    async fn wait_for_throttle(&mut self, bytes: usize) {
+        if let Some(throttle) = self.throttle.as_mut() {
+            throttle.consume(bytes).await;
+        }
+    }
+
+    fn start_part_upload(&mut self) {
         assert!(self.data.len() >= self.info.size_per_upload);
         let to_send = self.data.split_to(self.info.size_per_upload).freeze();
+        self.spawn_part_upload(to_send);
+    }
+
+    fn spawn_part_upload(&mut self, to_send: Bytes) {
+        assert!(self.upload_task.is_none());
         let part_num = (self.info.parts.len() + 1) as i32;
         eprintln!(
             "uploading {} bytes to {} (part {})",
-            self.info.size_per_upload, self.info.key, part_num
+            to_send.len(),
+            self.info.key,
+            part_num
         );
         let bytes_sent = to_send.len();
+        let delay = self.throttle.as_mut().map(|t| t.reserve(bytes_sent));
         let bucket = self.info.bucket.clone();
         let key = self.info.key.clone();
         let upload_id = self.info.upload_id.clone();
         let client = self.client.clone();
+        let config_override = self.options.config_override();
+        self.in_flight_bytes = bytes_sent;
+        self.publish_size();
         self.upload_task = Some(tokio::spawn(async move {
+            if let Some(delay) = delay {
+                tokio::time::sleep(delay).await;
+            }
             let part_upload = client
                 .upload_part()
                 .bucket(&bucket)
@@ -134,48 +871,153 @@ impl Upload {
                 .upload_id(&upload_id)
                 .part_number(part_num)
                 .body(to_send.into())
+                .customize()
+                .config_override(config_override)
                 .send()
-                .await?;
+                .await
+                .map_err(|e| part_failed(e, &key, &upload_id))?;
+            let part_id =
+                PartId::from_output(part_upload).ok_or(UploadSendError::NoPartId(part_num))?;
 
             Ok(UploadResult {
                 bytes_sent,
-                e_tag: part_upload.e_tag.unwrap(),
+                part_id,
             })
         }));
-
-        Ok(())
     }
 
-    async fn finish_part_upload(&mut self) -> Result<bool, SdkError<UploadPartError>> {
-        let mut upload_task = None;
-        std::mem::swap(&mut upload_task, &mut self.upload_task);
-        if let Some(upload_task) = upload_task {
-            let UploadResult { bytes_sent, e_tag } =
-                upload_task.await.expect("join failed on upload task")?;
-
+    fn record_part(
+        &mut self,
+        result: Result<UploadResult, UploadSendError>,
+    ) -> Result<bool, UploadSendError> {
+        let in_flight_bytes = std::mem::take(&mut self.in_flight_bytes);
+        let result = result.inspect_err(|_| self.release(in_flight_bytes));
+        if let Ok(UploadResult { bytes_sent, .. }) = result.as_ref() {
             self.info.uploaded_bytes += bytes_sent;
-            self.info.parts.push(e_tag);
-            Ok(true)
+        }
+        self.publish_size();
+        let UploadResult { part_id, .. } = result?;
+        self.info.parts.push(part_id);
+
+        Ok(true)
+    }
+
+    async fn finish_part_upload(&mut self) -> Result<bool, UploadSendError> {
+        if let Some(upload_task) = self.upload_task.take() {
+            let result = upload_task.await.expect("join failed on upload task");
+            self.record_part(result)
         } else {
             Ok(false)
         }
     }
 
-    pub async fn send(&mut self, data: Bytes) -> Result<bool, SdkError<UploadPartError>> {
+    /// Poll the part upload in flight, if any, to completion.
+    ///
+    /// Resolves to whether a part was completed.
+    pub fn poll_part_complete(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<bool, UploadSendError>> {
+        let Some(upload_task) = self.upload_task.as_mut() else {
+            return Poll::Ready(Ok(false));
+        };
+        let result = ready!(Pin::new(upload_task).poll(cx)).expect("join failed on upload task");
+        self.upload_task = None;
+        Poll::Ready(self.record_part(result))
+    }
+
+    /// Poll until the upload can take more data through `start_send`
+    /// without buffering more than a part's worth.
+    ///
+    /// Together with `start_send` and `poll_part_complete`, this drives
+    /// the upload from a custom scheduler rather than through `send`. Parts
+    /// and session refreshes still run as tokio tasks, as the sdk's http
+    /// client needs a tokio runtime anyway, so this has to be called within
+    /// the context of one, for instance through `Runtime::enter`.
+    pub fn poll_send_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), UploadSendError>> {
+        if tokio::runtime::Handle::try_current().is_err() {
+            return Poll::Ready(Err(UploadSendError::NoRuntime));
+        }
+        ready!(self.poll_refresh(cx))?;
+        while self.data.len() >= self.info.size_per_upload {
+            if self.upload_task.is_some() {
+                ready!(self.poll_part_complete(cx))?;
+            }
+            self.start_part_upload();
+        }
+
+        Poll::Ready(Ok(()))
+    }
+
+    /// Buffer data for upload. Call `poll_send_ready` before every call to
+    /// this, to actually start uploading parts and to refresh the session
+    /// when it is expiring.
+    pub fn start_send(&mut self, data: Bytes) -> Result<(), UploadSendError> {
+        if self.refresh_task.is_some() || self.info.refresh_due(self.max_session_duration) {
+            return Err(UploadSendError::RefreshPending);
+        }
+        if let Some(quota) = self.quota.as_ref() {
+            quota.charge(&self.info.key, data.len())?;
+        }
+        self.data.extend(data);
+        self.publish_size();
+
+        Ok(())
+    }
+
+    pub async fn send(&mut self, data: Bytes) -> Result<bool, UploadSendError> {
+        self.refresh_if_expiring().await?;
+        if let Some(quota) = self.quota.as_ref() {
+            quota.charge(&self.info.key, data.len())?;
+        }
         let mut something_happened = false;
         self.data.extend(data);
+        self.publish_size();
         if self.upload_task.is_some() && self.upload_task.as_ref().unwrap().is_finished() {
             something_happened = self.finish_part_upload().await?;
         }
         while self.data.len() >= self.info.size_per_upload {
-            something_happened = something_happened || self.finish_part_upload().await?;
-            self.start_part_upload().await?;
+            // always wait for the part in flight, only one runs at a time
+            let finished = self.finish_part_upload().await?;
+            something_happened = something_happened || finished;
+            self.start_part_upload();
+        }
+
+        Ok(something_happened)
+    }
+
+    /// Send the first `len` bytes of a buffer from `pool`.
+    ///
+    /// A buffer of at least a part's size, sent while there's no other data
+    /// waiting, is uploaded as its own part without copying, and goes back to
+    /// the pool once it's uploaded. Anything else is copied in like with
+    /// `send`, and the buffer goes back right away.
+    pub async fn send_buffer<P>(
+        &mut self,
+        pool: Arc<P>,
+        buffer: P::Buffer,
+        len: usize,
+    ) -> Result<bool, UploadSendError>
+    where
+        P: BufferPool + 'static,
+        P::Buffer: 'static,
+    {
+        self.refresh_if_expiring().await?;
+        let pooled = Pooled::new(pool, buffer, len);
+        if !self.data.is_empty() || len < self.info.size_per_upload {
+            return self.send(Bytes::copy_from_slice(pooled.as_ref())).await;
+        }
+
+        if let Some(quota) = self.quota.as_ref() {
+            quota.charge(&self.info.key, len)?;
         }
+        let something_happened = self.finish_part_upload().await?;
+        self.spawn_part_upload(pooled.into_bytes());
 
         Ok(something_happened)
     }
 
-    async fn send_final(&mut self) -> Result<(), SdkError<UploadPartError>> {
+    async fn send_final(&mut self) -> Result<(), UploadSendError> {
         self.finish_part_upload().await?;
         if self.data.is_empty() {
             return Ok(());
@@ -183,8 +1025,11 @@ impl Upload {
         let part_num = (self.info.parts.len() + 1) as i32;
         eprintln!(
             "uploading final {} bytes to {} (part {})",
-            self.info.size_per_upload, self.info.key, part_num
+            self.data.len(),
+            self.info.key,
+            part_num
         );
+        self.wait_for_throttle(self.data.len()).await;
         let part_upload = self
             .client
             .upload_part()
@@ -193,80 +1038,181 @@ impl Upload {
             .upload_id(&self.info.upload_id)
             .part_number(part_num)
             .body(self.data.clone().freeze().into())
+            .customize()
+            .config_override(self.options.config_override())
             .send()
-            .await?;
-
-        let e_tag = part_upload.e_tag.unwrap();
-        self.info.parts.push(e_tag);
+            .await
+            .map_err(|e| part_failed(e, &self.info.key, &self.info.upload_id))
+            .and_then(|o| PartId::from_output(o).ok_or(UploadSendError::NoPartId(part_num)));
+        let part_id = match part_upload {
+            Ok(part_id) => part_id,
+            Err(e) => {
+                self.release(self.data.len());
+                self.data.clear();
+                self.publish_size();
+                return Err(e);
+            }
+        };
+        self.info.parts.push(part_id);
+        self.info.uploaded_bytes += self.data.len();
+        self.data.clear();
+        self.publish_size();
 
         Ok(())
     }
 
     pub async fn complete(mut self) -> Result<(), UploadCompleteError> {
+        // a session left completed by a failed refresh can't take the final part
+        self.refresh_if_expiring()
+            .await
+            .map_err(UploadCompleteError::FinalPartFailed)?;
         self.send_final()
             .await
             .map_err(UploadCompleteError::FinalPartFailed)?;
-        let Self {
-            client,
-            info:
-                UploadInfo {
-                    bucket,
+        self.complete_parts().await.map_err(|e| {
+            if is_no_such_upload(&e) {
+                UploadCompleteError::Expired(self.expired())
+            } else {
+                UploadCompleteError::CompletionFailed(e)
+            }
+        })?;
+        self.release(self.replaced_bytes);
+        if let Some((key_mapping, key)) = self.key_record.as_ref() {
+            key_mapping
+                .record(
+                    &self.client,
+                    &self.info.bucket,
                     key,
-                    upload_id,
-                    parts,
-                    ..
-                },
-            ..
-        } = self;
-
-        let parts: Vec<_> = parts
-            .into_iter()
-            .enumerate()
-            .map(|(ix, e_tag)| {
-                CompletedPart::builder()
-                    .part_number((ix + 1) as i32)
-                    .e_tag(e_tag)
-                    .build()
-            })
-            .collect();
+                    &self.info.key,
+                    self.auditor.as_ref(),
+                    self.quota.as_deref(),
+                )
+                .await?;
+        }
 
-        client
-            .complete_multipart_upload()
-            .bucket(bucket)
-            .key(key)
-            .upload_id(upload_id)
-            .multipart_upload(
-                CompletedMultipartUpload::builder()
-                    .set_parts(Some(parts))
-                    .build(),
-            )
+        Ok(())
+    }
+
+    async fn complete_parts(
+        &self,
+    ) -> Result<CompleteMultipartUploadOutput, SdkError<CompleteMultipartUploadError>> {
+        complete_session(
+            &self.client,
+            &self.info,
+            &self.options,
+            self.auditor.as_ref(),
+        )
+        .await
+    }
+
+    pub fn size(&self) -> ShardSize {
+        ShardSize {
+            buffered: self.data.len() + self.in_flight_bytes,
+            uploaded: self.info.uploaded_bytes,
+        }
+    }
+
+    fn publish_size(&self) {
+        *self.published_size.lock().unwrap() = self.size();
+    }
+
+    /// Abort this upload, discarding all parts uploaded so far.
+    pub async fn abort(mut self) -> Result<(), SdkError<AbortMultipartUploadError>> {
+        let charged = self.info.uploaded_bytes + self.in_flight_bytes + self.data.len();
+        if let Some(upload_task) = self.upload_task.take() {
+            upload_task.abort();
+        }
+        if let Some(refresh_task) = self.refresh_task.take() {
+            refresh_task.abort();
+        }
+        let result = self
+            .client
+            .abort_multipart_upload()
+            .bucket(&self.info.bucket)
+            .key(&self.info.key)
+            .upload_id(&self.info.upload_id)
+            .customize()
+            .config_override(self.options.config_override())
             .send()
-            .await
-            .map_err(UploadCompleteError::CompletionFailed)?;
+            .await;
+        if let Some(auditor) = self.auditor.as_ref() {
+            let operation = AuditOperation::AbortMultipartUpload {
+                key: self.info.key.clone(),
+                upload_id: self.info.upload_id.clone(),
+            };
+            auditor.record(&self.info.bucket, operation, &result).await;
+        }
+        result?;
+        if let Some(quota) = self.quota.as_ref() {
+            quota.release(&self.info.key, charged);
+        }
 
         Ok(())
     }
 }
 
+/// How much of a shard's data is where.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ShardSize {
+    /// Bytes held in memory, including a part that is still being uploaded.
+    pub buffered: usize,
+    /// Bytes in completed parts.
+    pub uploaded: usize,
+}
+
+impl ShardSize {
+    pub fn total(&self) -> usize {
+        self.buffered + self.uploaded
+    }
+}
+
+/// How far the largest shard is ahead of the average, as a ratio.
+///
+/// This is 1.0 when all shards are the same size, and also when there is
+/// no data at all.
+pub fn skew(sizes: &[ShardSize]) -> f64 {
+    let total: usize = sizes.iter().map(ShardSize::total).sum();
+    if total == 0 {
+        return 1.0;
+    }
+    let max = sizes.iter().map(ShardSize::total).max().unwrap_or(0);
+    let mean = total as f64 / sizes.len() as f64;
+
+    max as f64 / mean
+}
+
 pub struct Uploads {
     uploads: Vec<Mutex<Upload>>,
+    /// The published sizes of the uploads, so they can be read while a part
+    /// upload holds an upload's lock.
+    sizes: Vec<Arc<std::sync::Mutex<ShardSize>>>,
 }
 
 impl Uploads {
+    pub fn from_uploads(uploads: Vec<Upload>) -> Self {
+        Self {
+            sizes: uploads
+                .iter()
+                .map(|upload| upload.published_size.clone())
+                .collect(),
+            uploads: uploads.into_iter().map(Mutex::new).collect(),
+        }
+    }
+
     pub async fn new(
         client: Arc<Client>,
         bucket: String,
         prefix: String,
         amount: usize,
-    ) -> Result<Self, aws_sdk_s3::Error> {
+    ) -> Result<Self, UploadCreateError> {
         let mut uploads = Vec::with_capacity(amount);
         for index in 0..amount {
             let upload =
                 Upload::new(client.clone(), bucket.clone(), format!("{prefix}{index}")).await?;
-            uploads.push(Mutex::new(upload));
+            uploads.push(upload);
         }
 
-        Ok(Self { uploads })
+        Ok(Self::from_uploads(uploads))
     }
 
     pub async fn new_with_size(
@@ -275,7 +1221,7 @@ impl Uploads {
         prefix: String,
         amount: usize,
         size_per_upload: usize,
-    ) -> Result<Self, aws_sdk_s3::Error> {
+    ) -> Result<Self, UploadCreateError> {
         let mut uploads = Vec::with_capacity(amount);
         for index in 0..amount {
             let upload = Upload::new_with_size(
@@ -285,13 +1231,13 @@ impl Uploads {
                 size_per_upload,
             )
             .await?;
-            uploads.push(Mutex::new(upload));
+            uploads.push(upload);
         }
 
-        Ok(Self { uploads })
+        Ok(Self::from_uploads(uploads))
     }
 
-    pub async fn send(&self, index: usize, data: Bytes) -> Result<(), aws_sdk_s3::Error> {
+    pub async fn send(&self, index: usize, data: Bytes) -> Result<(), UploadSendError> {
         let mut upload = self.uploads[index].lock().await;
 
         upload.send(data).await?;
@@ -299,6 +1245,38 @@ impl Uploads {
         Ok(())
     }
 
+    pub async fn send_buffer<P>(
+        &self,
+        index: usize,
+        pool: Arc<P>,
+        buffer: P::Buffer,
+        len: usize,
+    ) -> Result<(), UploadSendError>
+    where
+        P: BufferPool + 'static,
+        P::Buffer: 'static,
+    {
+        let mut upload = self.uploads[index].lock().await;
+
+        upload.send_buffer(pool, buffer, len).await?;
+
+        Ok(())
+    }
+
+    /// The shard sizes as of the last change to each, without waiting for
+    /// parts in flight.
+    pub fn sizes(&self) -> Vec<ShardSize> {
+        self.sizes
+            .iter()
+            .map(|size| *size.lock().unwrap())
+            .collect()
+    }
+
+    /// The skew of the current shard sizes, see `skew`.
+    pub fn skew(&self) -> f64 {
+        skew(&self.sizes())
+    }
+
     pub async fn complete(self) -> Result<(), UploadCompleteError> {
         for lock in self.uploads {
             let upload = lock.into_inner();
@@ -0,0 +1,47 @@
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use vl_aws_util::audit::{
+    AuditContext, AuditError, AuditEvent, AuditOperation, AuditSink, Auditor,
+};
+
+/// A sink that can't write anything.
+struct FailingSink;
+
+#[async_trait]
+impl AuditSink for FailingSink {
+    async fn record(&self, _event: &AuditEvent) -> Result<(), AuditError> {
+        Err(std::io::Error::other("disk full").into())
+    }
+}
+
+#[tokio::test]
+async fn failed_events_reach_the_failure_handler() {
+    let failed = Arc::new(Mutex::new(Vec::new()));
+    let handler_failed = failed.clone();
+    let auditor = Auditor::new(Arc::new(FailingSink), AuditContext::default())
+        .with_failure_handler(Arc::new(move |event: &AuditEvent, error: &AuditError| {
+            handler_failed
+                .lock()
+                .unwrap()
+                .push((event.clone(), error.to_string()));
+        }))
+        .with_context(AuditContext {
+            task_id: Some("task".to_string()),
+            tenant: None,
+        });
+
+    let operation = AuditOperation::Delete {
+        key: "key".to_string(),
+    };
+    auditor
+        .record("bucket", operation, &Ok::<_, AuditError>(()))
+        .await;
+
+    let failed = failed.lock().unwrap();
+    assert_eq!(failed.len(), 1);
+    let (event, error) = &failed[0];
+    assert!(matches!(&event.operation, AuditOperation::Delete { key } if key == "key"));
+    assert_eq!(event.context.task_id.as_deref(), Some("task"));
+    assert!(error.contains("disk full"));
+}
@@ -0,0 +1,83 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::stream::{self, StreamExt};
+use futures::Stream;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use vl_aws_util::broadcast::{broadcast_chunks, BroadcastError, SlowConsumerPolicy};
+
+fn chunk(index: usize) -> Bytes {
+    Bytes::from(index.to_string())
+}
+
+fn source(count: usize) -> impl Stream<Item = Result<Bytes, &'static str>> {
+    stream::iter((0..count).map(|index| Ok(chunk(index))))
+}
+
+#[tokio::test]
+async fn waiting_holds_back_fast_consumers_for_slow_ones() {
+    let mut consumers = broadcast_chunks(source(10), 2, 1, SlowConsumerPolicy::Wait).into_iter();
+    let fast = consumers.next().unwrap();
+    let slow = consumers.next().unwrap().then(|item| async move {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        item
+    });
+
+    let (fast, slow): (Vec<_>, Vec<_>) = tokio::join!(fast.collect(), slow.collect());
+    let expected: Vec<_> = (0..10).map(chunk).collect();
+    assert_eq!(
+        fast.into_iter().map(Result::unwrap).collect::<Vec<_>>(),
+        expected
+    );
+    assert_eq!(
+        slow.into_iter().map(Result::unwrap).collect::<Vec<_>>(),
+        expected
+    );
+}
+
+#[tokio::test]
+async fn disconnected_consumers_end_with_lagged() {
+    let (tx, rx) = mpsc::channel::<Result<Bytes, &str>>(1);
+    let mut consumers = broadcast_chunks(
+        ReceiverStream::new(rx),
+        2,
+        2,
+        SlowConsumerPolicy::Disconnect,
+    )
+    .into_iter();
+    let mut reading = Box::pin(consumers.next().unwrap());
+    let idle = consumers.next().unwrap();
+
+    // the reading consumer keeps up, the idle one never reads
+    for index in 0..5 {
+        tx.send(Ok(chunk(index))).await.unwrap();
+        assert_eq!(reading.next().await.unwrap().unwrap(), chunk(index));
+    }
+    drop(tx);
+    assert!(reading.next().await.is_none());
+
+    let idle: Vec<_> = idle.collect().await;
+    assert_eq!(idle.len(), 3);
+    assert_eq!(idle[0].as_ref().unwrap(), &chunk(0));
+    assert_eq!(idle[1].as_ref().unwrap(), &chunk(1));
+    assert!(matches!(idle[2], Err(BroadcastError::Lagged)));
+}
+
+#[tokio::test]
+async fn source_errors_reach_every_consumer() {
+    for policy in [SlowConsumerPolicy::Wait, SlowConsumerPolicy::Disconnect] {
+        let source = stream::iter([Ok(chunk(0)), Err("source failed"), Ok(chunk(1))]);
+        let consumers = broadcast_chunks(source, 3, 4, policy);
+
+        for consumer in consumers {
+            let items: Vec<_> = consumer.collect().await;
+            assert_eq!(items.len(), 2, "{policy:?}");
+            assert_eq!(items[0].as_ref().unwrap(), &chunk(0));
+            assert!(
+                matches!(&items[1], Err(BroadcastError::Source(e)) if **e == "source failed"),
+                "{policy:?}"
+            );
+        }
+    }
+}
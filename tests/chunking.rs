@@ -0,0 +1,127 @@
+mod common;
+
+use std::sync::atomic::Ordering;
+
+use aws_sdk_s3::primitives::{ByteStream, SdkBody};
+use bytes::Bytes;
+use futures::StreamExt;
+use proptest::prelude::*;
+use vl_aws_util::chunk::{PartialChunk, Rechunker};
+use vl_aws_util::download::{self, VecStreamError};
+
+/// Feed `pieces` through a rechunker the way `stream_vecs` does.
+fn reassemble(
+    chunk_size: usize,
+    count: Option<usize>,
+    pieces: &[Vec<u8>],
+) -> (Vec<Bytes>, Result<(), PartialChunk>) {
+    let mut rechunker = Rechunker::new(chunk_size, count);
+    let mut chunks = Vec::new();
+    let mut pieces = pieces.iter();
+    loop {
+        while let Some(chunk) = rechunker.next_chunk() {
+            chunks.push(chunk);
+        }
+        if rechunker.is_done() {
+            break;
+        }
+        match pieces.next() {
+            Some(piece) => rechunker.push(Bytes::copy_from_slice(piece)),
+            None => break,
+        }
+    }
+
+    (chunks, rechunker.finish())
+}
+
+/// Some data, split at arbitrary points.
+fn split_data() -> impl Strategy<Value = Vec<Vec<u8>>> {
+    prop::collection::vec(prop::collection::vec(any::<u8>(), 0..40), 0..20)
+}
+
+proptest! {
+    #[test]
+    fn chunks_are_the_input_in_order(
+        chunk_size in 1usize..32,
+        count in prop::option::of(0usize..40),
+        pieces in split_data(),
+    ) {
+        let input = pieces.concat();
+        let (chunks, result) = reassemble(chunk_size, count, &pieces);
+
+        let whole = input.len() / chunk_size;
+        let expected_count = count.map_or(whole, |c| c.min(whole));
+        prop_assert_eq!(chunks.len(), expected_count);
+        for chunk in &chunks {
+            prop_assert_eq!(chunk.len(), chunk_size);
+        }
+        prop_assert_eq!(chunks.concat(), &input[..expected_count * chunk_size]);
+
+        let done = count == Some(expected_count);
+        if done || input.len().is_multiple_of(chunk_size) {
+            prop_assert_eq!(result, Ok(()));
+        } else {
+            prop_assert_eq!(
+                result,
+                Err(PartialChunk {
+                    len: input.len() - expected_count * chunk_size,
+                    chunk_size,
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn splitting_does_not_change_the_output(
+        chunk_size in 1usize..32,
+        count in prop::option::of(0usize..40),
+        pieces in split_data(),
+    ) {
+        let whole = reassemble(chunk_size, count, &[pieces.concat()]);
+        let split = reassemble(chunk_size, count, &pieces);
+        prop_assert_eq!(whole, split);
+    }
+}
+
+#[test]
+fn zero_count_yields_nothing() {
+    let (chunks, result) = reassemble(4, Some(0), &[vec![1, 2, 3, 4, 5]]);
+    assert!(chunks.is_empty());
+    assert_eq!(result, Ok(()));
+}
+
+#[tokio::test]
+async fn truncated_bodies_end_the_stream_with_an_error() {
+    let body = ByteStream::from_static(b"0123456789");
+    let items: Vec<_> = download::stream_vecs(body, 4, None).await.collect().await;
+
+    assert_eq!(items.len(), 3);
+    assert_eq!(items[0].as_ref().unwrap(), &Bytes::from_static(b"0123"));
+    assert_eq!(items[1].as_ref().unwrap(), &Bytes::from_static(b"4567"));
+    assert!(matches!(
+        &items[2],
+        Err(VecStreamError::PartialChunk(PartialChunk {
+            len: 2,
+            chunk_size: 4
+        }))
+    ));
+}
+
+#[tokio::test]
+async fn truncated_objects_are_not_fetched_again() {
+    let (client, requests) = common::responding_client(|_| {
+        http::Response::builder()
+            .status(206)
+            .body(SdkBody::from("0123456789"))
+            .unwrap()
+    });
+    let items: Vec<_> =
+        download::stream_vecs_from(client, "bucket".to_string(), "key".to_string(), 0, None, 4)
+            .await
+            .collect()
+            .await;
+
+    assert_eq!(items.len(), 3);
+    assert!(matches!(&items[2], Err(VecStreamError::PartialChunk(_))));
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,67 @@
+//! Offline clients shared by the tests.
+#![allow(dead_code)]
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use aws_sdk_s3::config::{retry::RetryConfig, BehaviorVersion, Credentials, Region};
+use aws_sdk_s3::{primitives::SdkBody, Client, Config};
+use aws_smithy_http_client::test_util::{infallible_client_fn, NeverClient};
+use vl_aws_util::store::S3Store;
+
+/// Test credentials in us-east-1, without retries.
+pub fn config() -> aws_sdk_s3::config::Builder {
+    Config::builder()
+        .behavior_version(BehaviorVersion::latest())
+        .region(Region::new("us-east-1"))
+        .credentials_provider(Credentials::new("test", "test", None, None, "test"))
+        .retry_config(RetryConfig::disabled())
+}
+
+/// A client that is never sent any requests.
+pub fn client() -> Arc<Client> {
+    Arc::new(Client::from_conf(config().build()))
+}
+
+/// A client whose requests all fail right away.
+pub fn unreachable_client() -> Arc<Client> {
+    Arc::new(Client::from_conf(
+        config().endpoint_url("http://127.0.0.1:1").build(),
+    ))
+}
+
+/// A client whose requests never get a response.
+pub fn hanging_client() -> Arc<Client> {
+    Arc::new(Client::from_conf(
+        config()
+            .endpoint_url("http://127.0.0.1:1")
+            .http_client(NeverClient::new())
+            .build(),
+    ))
+}
+
+/// A store on `client`, in a bucket named "bucket".
+pub fn store() -> S3Store {
+    S3Store::try_new(client(), "bucket").unwrap()
+}
+
+/// A client answering every request with a response built by `respond`,
+/// along with a count of the requests it was sent.
+pub fn responding_client(
+    respond: impl Fn(&http::Request<SdkBody>) -> http::Response<SdkBody> + Send + Sync + 'static,
+) -> (Arc<Client>, Arc<AtomicUsize>) {
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = requests.clone();
+    let http_client = infallible_client_fn(move |request| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        respond(&request)
+    });
+    let config = config()
+        .endpoint_url("http://127.0.0.1:1")
+        .http_client(http_client)
+        .build();
+
+    (Arc::new(Client::from_conf(config)), requests)
+}
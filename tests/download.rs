@@ -0,0 +1,99 @@
+mod common;
+
+use std::sync::{atomic::Ordering, Arc};
+
+use aws_sdk_s3::primitives::SdkBody;
+use futures::StreamExt;
+use vl_aws_util::download::{self, DownloadVecError, VecStreamError};
+use vl_aws_util::options::OpOptions;
+use vl_aws_util::pool::VecPool;
+
+/// An S3 error response for a missing key.
+fn no_such_key() -> http::Response<SdkBody> {
+    http::Response::builder()
+        .status(404)
+        .body(SdkBody::from(
+            "<Error><Code>NoSuchKey</Code><Message>missing</Message></Error>",
+        ))
+        .unwrap()
+}
+
+fn object(data: &'static str) -> http::Response<SdkBody> {
+    http::Response::builder()
+        .status(206)
+        .body(SdkBody::from(data))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn missing_keys_are_not_found_when_streaming_into_buffers() {
+    let (client, _) = common::responding_client(|_| no_such_key());
+    let pool = Arc::new(VecPool::new(8, 2));
+    let items: Vec<_> = download::stream_into_buffers(
+        client,
+        "bucket".to_string(),
+        "key".to_string(),
+        pool,
+        0,
+        None,
+        4,
+        OpOptions::default(),
+    )
+    .await
+    .collect()
+    .await;
+
+    assert_eq!(items.len(), 1);
+    assert!(matches!(&items[0], Err(VecStreamError::NotFound(key)) if key == "key"));
+}
+
+#[tokio::test]
+async fn try_stream_returns_none_for_missing_keys() {
+    let (client, requests) = common::responding_client(|_| no_such_key());
+    let stream =
+        download::try_stream_vecs_from(client, "bucket".to_string(), "key".to_string(), 0, None, 4)
+            .await
+            .unwrap();
+
+    assert!(stream.is_none());
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn try_stream_reads_its_first_request() {
+    let (client, requests) = common::responding_client(|request| {
+        assert_eq!(request.method(), http::Method::GET);
+        object("01234567")
+    });
+    let stream =
+        download::try_stream_vecs_from(client, "bucket".to_string(), "key".to_string(), 0, None, 4)
+            .await
+            .unwrap()
+            .unwrap();
+    let chunks: Vec<_> = stream.map(Result::unwrap).collect().await;
+
+    assert_eq!(chunks, vec!["0123", "4567"]);
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn objects_without_a_content_length_are_an_error() {
+    // responses are only given a content-length header if it's set explicitly
+    let (client, _) = common::responding_client(|_| object("data"));
+
+    assert!(matches!(
+        download::download_vec::<u8>(&client, "bucket", "key").await,
+        Err(DownloadVecError::NoContentLength(key)) if key == "key"
+    ));
+}
+
+#[tokio::test]
+async fn zero_sized_elements_are_rejected() {
+    let (client, requests) = common::responding_client(|_| object("data"));
+
+    assert!(matches!(
+        download::download_vec::<()>(&client, "bucket", "key").await,
+        Err(DownloadVecError::ZeroSizedElement)
+    ));
+    assert_eq!(requests.load(Ordering::SeqCst), 0);
+}
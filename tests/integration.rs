@@ -0,0 +1,616 @@
+//! End-to-end tests against a MinIO container.
+//!
+//! These need a docker daemon, so they only build with the
+//! `integration-tests` feature:
+//!
+//!     cargo test --features integration-tests --test integration
+#![cfg(feature = "integration-tests")]
+
+use std::pin::pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use aws_sdk_s3::config::{
+    interceptors::BeforeTransmitInterceptorContextRef, retry::RetryConfig, timeout::TimeoutConfig,
+    BehaviorVersion, ConfigBag, Credentials, Intercept, Region, RuntimeComponents,
+    StalledStreamProtectionConfig,
+};
+use aws_sdk_s3::error::BoxError;
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+use aws_sdk_s3::Client;
+use bytes::Bytes;
+use futures::StreamExt;
+use testcontainers_modules::minio::MinIO;
+use testcontainers_modules::testcontainers::runners::AsyncRunner;
+use testcontainers_modules::testcontainers::ContainerAsync;
+use vl_aws_util::compress::{self, CompressionError, Dictionary};
+use vl_aws_util::download::{self, DownloadVecError, VecStreamError};
+use vl_aws_util::names::BucketName;
+use vl_aws_util::options::OpOptions;
+use vl_aws_util::scoped::ScopedStore;
+use vl_aws_util::session::{DownloadCursor, DownloadSession, SessionError};
+use vl_aws_util::store::S3Store;
+use vl_aws_util::upload::{Upload, UploadInfo, Uploads};
+
+/// S3 requires every part but the last to be at least this big.
+const PART_SIZE: usize = 5 << 20;
+const BUCKET: &str = "test";
+
+struct Harness {
+    container: ContainerAsync<MinIO>,
+    client: Arc<Client>,
+}
+
+impl Harness {
+    async fn start() -> Self {
+        let container = MinIO::default()
+            .start()
+            .await
+            .expect("could not start minio");
+        let host = container.get_host().await.unwrap();
+        let port = container.get_host_port_ipv4(9000).await.unwrap();
+
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-east-1"))
+            .endpoint_url(format!("http://{host}:{port}"))
+            .credentials_provider(Credentials::new(
+                "minioadmin",
+                "minioadmin",
+                None,
+                None,
+                "test",
+            ))
+            .force_path_style(true)
+            // fail fast once the container is paused, so failures are observable
+            .stalled_stream_protection(
+                StalledStreamProtectionConfig::enabled()
+                    .grace_period(Duration::from_secs(1))
+                    .build(),
+            )
+            .timeout_config(
+                TimeoutConfig::builder()
+                    .operation_attempt_timeout(Duration::from_secs(2))
+                    .build(),
+            )
+            .retry_config(RetryConfig::standard().with_max_attempts(1))
+            .build();
+        let client = Arc::new(Client::from_conf(config));
+        client.create_bucket().bucket(BUCKET).send().await.unwrap();
+
+        Self { container, client }
+    }
+
+    async fn put(&self, key: &str, data: &[u8]) {
+        self.client
+            .put_object()
+            .bucket(BUCKET)
+            .key(key)
+            .body(data.to_vec().into())
+            .send()
+            .await
+            .unwrap();
+    }
+
+    async fn get(&self, key: &str) -> Vec<u8> {
+        download::download_vec(&self.client, BUCKET, key)
+            .await
+            .unwrap()
+    }
+}
+
+fn test_data(len: usize, seed: u8) -> Vec<u8> {
+    (0..len)
+        .map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed))
+        .collect()
+}
+
+#[tokio::test]
+async fn upload_resumes_from_persisted_info() {
+    let harness = Harness::start().await;
+    let data = test_data(3 * PART_SIZE + 1234, 1);
+
+    let mut upload = Upload::new_with_size(
+        harness.client.clone(),
+        BUCKET.to_string(),
+        "resumed".to_string(),
+        PART_SIZE,
+    )
+    .await
+    .unwrap();
+    upload
+        .send(Bytes::copy_from_slice(&data[..2 * PART_SIZE + 10]))
+        .await
+        .unwrap();
+
+    // simulate a crash: persist what is known to be uploaded and drop the rest
+    let persisted = serde_json::to_string(&upload.info).unwrap();
+    drop(upload);
+
+    let info: UploadInfo = serde_json::from_str(&persisted).unwrap();
+    let resume_from = info.uploaded_bytes;
+    assert!(resume_from > 0 && resume_from.is_multiple_of(PART_SIZE));
+    let mut upload = Upload::new_from_info(harness.client.clone(), info);
+    upload
+        .send(Bytes::copy_from_slice(&data[resume_from..]))
+        .await
+        .unwrap();
+    upload.complete().await.unwrap();
+
+    assert_eq!(harness.get("resumed").await, data);
+}
+
+#[tokio::test]
+async fn refreshes_keep_data_under_keys_needing_encoding() {
+    let harness = Harness::start().await;
+    let data = test_data(2 * PART_SIZE + 99, 6);
+    let key = "refreshed key+ü";
+
+    // every send finds the session expiring
+    let mut upload = Upload::new_with_size(
+        harness.client.clone(),
+        BUCKET.to_string(),
+        key.to_string(),
+        PART_SIZE,
+    )
+    .await
+    .unwrap()
+    .with_max_session_duration(Duration::ZERO);
+    for piece in data.chunks(PART_SIZE / 2 + 7) {
+        upload.send(Bytes::copy_from_slice(piece)).await.unwrap();
+    }
+    upload.complete().await.unwrap();
+
+    assert_eq!(harness.get(key).await, data);
+}
+
+#[tokio::test]
+async fn sessions_of_unknown_age_are_looked_up() {
+    let harness = Harness::start().await;
+    let data = test_data(PART_SIZE + 4321, 7);
+
+    let mut upload = Upload::new_with_size(
+        harness.client.clone(),
+        BUCKET.to_string(),
+        "ageless".to_string(),
+        PART_SIZE,
+    )
+    .await
+    .unwrap();
+    upload
+        .send(Bytes::copy_from_slice(&data[..PART_SIZE + 1]))
+        .await
+        .unwrap();
+    // this waits for the part in flight, so the info covers it
+    upload.refresh().await.unwrap();
+    let upload_id = upload.info.upload_id().to_string();
+
+    // as saved before the creation time was recorded
+    let mut persisted = serde_json::to_value(&upload.info).unwrap();
+    persisted["created_at"] = serde_json::Value::Null;
+    drop(upload);
+    let info: UploadInfo = serde_json::from_value(persisted).unwrap();
+    assert!(info.age().is_none());
+
+    let resume_from = info.uploaded_bytes;
+    let mut upload = Upload::new_from_info(harness.client.clone(), info)
+        .with_max_session_duration(Duration::from_secs(3600));
+    upload
+        .send(Bytes::copy_from_slice(&data[resume_from..]))
+        .await
+        .unwrap();
+    assert!(upload.info.age().unwrap() < Duration::from_secs(60));
+    assert_eq!(upload.info.upload_id(), upload_id);
+    upload.complete().await.unwrap();
+
+    assert_eq!(harness.get("ageless").await, data);
+}
+
+#[tokio::test]
+async fn refresh_recovers_from_a_completed_session() {
+    let harness = Harness::start().await;
+    let data = test_data(PART_SIZE + 555, 8);
+    let key = "half-refreshed";
+
+    let mut upload = Upload::new_with_size(
+        harness.client.clone(),
+        BUCKET.to_string(),
+        key.to_string(),
+        PART_SIZE,
+    )
+    .await
+    .unwrap();
+    upload
+        .send(Bytes::copy_from_slice(&data[..PART_SIZE + 1]))
+        .await
+        .unwrap();
+    // this waits for the part in flight, so the info covers it
+    upload.refresh().await.unwrap();
+    let mut persisted = serde_json::to_value(&upload.info).unwrap();
+    drop(upload);
+
+    // a refresh that completed the session, then failed to copy it
+    let parts = harness
+        .client
+        .list_parts()
+        .bucket(BUCKET)
+        .key(key)
+        .upload_id(persisted["upload_id"].as_str().unwrap())
+        .send()
+        .await
+        .unwrap();
+    let completed = harness
+        .client
+        .complete_multipart_upload()
+        .bucket(BUCKET)
+        .key(key)
+        .upload_id(persisted["upload_id"].as_str().unwrap())
+        .multipart_upload(
+            CompletedMultipartUpload::builder()
+                .set_parts(Some(
+                    parts
+                        .parts()
+                        .iter()
+                        .map(|part| {
+                            CompletedPart::builder()
+                                .set_part_number(part.part_number())
+                                .set_e_tag(part.e_tag().map(str::to_string))
+                                .build()
+                        })
+                        .collect(),
+                ))
+                .build(),
+        )
+        .send()
+        .await
+        .unwrap();
+    persisted["completed"] = serde_json::json!({ "e_tag": completed.e_tag() });
+    let info: UploadInfo = serde_json::from_value(persisted).unwrap();
+
+    let resume_from = info.uploaded_bytes;
+    let mut upload = Upload::new_from_info(harness.client.clone(), info);
+    upload
+        .send(Bytes::copy_from_slice(&data[resume_from..]))
+        .await
+        .unwrap();
+    upload.complete().await.unwrap();
+
+    assert_eq!(harness.get(key).await, data);
+}
+
+#[tokio::test]
+async fn sends_spanning_several_parts_upload_all_of_them() {
+    let harness = Harness::start().await;
+    let data = test_data(7 * PART_SIZE + 99, 6);
+
+    let mut upload = Upload::new_with_size(
+        harness.client.clone(),
+        BUCKET.to_string(),
+        "large-sends".to_string(),
+        PART_SIZE,
+    )
+    .await
+    .unwrap();
+    // the first send leaves a part in flight for the second to wait on
+    upload
+        .send(Bytes::copy_from_slice(&data[..3 * PART_SIZE]))
+        .await
+        .unwrap();
+    upload
+        .send(Bytes::copy_from_slice(&data[3 * PART_SIZE..]))
+        .await
+        .unwrap();
+    upload.complete().await.unwrap();
+
+    assert_eq!(harness.get("large-sends").await, data);
+}
+
+#[tokio::test]
+async fn multi_shard_uploads_complete() {
+    let harness = Harness::start().await;
+    let shards: Vec<Vec<u8>> = (0..3)
+        .map(|i| test_data(PART_SIZE + 1000 * i, i as u8))
+        .collect();
+
+    let uploads = Arc::new(
+        Uploads::new_with_size(
+            harness.client.clone(),
+            BUCKET.to_string(),
+            "shards/".to_string(),
+            shards.len(),
+            PART_SIZE,
+        )
+        .await
+        .unwrap(),
+    );
+    let mut tasks = Vec::new();
+    for (index, shard) in shards.iter().enumerate() {
+        let uploads = uploads.clone();
+        let shard = shard.clone();
+        tasks.push(tokio::spawn(async move {
+            for piece in shard.chunks(1 << 20) {
+                uploads
+                    .send(index, Bytes::copy_from_slice(piece))
+                    .await
+                    .unwrap();
+            }
+        }));
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+    let uploads = Arc::into_inner(uploads).unwrap();
+    uploads.complete().await.unwrap();
+
+    for (index, shard) in shards.iter().enumerate() {
+        assert_eq!(&harness.get(&format!("shards/{index}")).await, shard);
+    }
+}
+
+#[tokio::test]
+async fn aborted_upload_leaves_nothing_behind() {
+    let harness = Harness::start().await;
+    let mut upload = Upload::new_with_size(
+        harness.client.clone(),
+        BUCKET.to_string(),
+        "aborted".to_string(),
+        PART_SIZE,
+    )
+    .await
+    .unwrap();
+    upload
+        .send(Bytes::from(test_data(PART_SIZE + 1, 2)))
+        .await
+        .unwrap();
+    upload.abort().await.unwrap();
+
+    let listing = harness
+        .client
+        .list_multipart_uploads()
+        .bucket(BUCKET)
+        .send()
+        .await
+        .unwrap();
+    assert!(listing.uploads().is_empty());
+    assert!(matches!(
+        download::download_vec::<u8>(&harness.client, BUCKET, "aborted").await,
+        Err(DownloadVecError::NotFound(_))
+    ));
+}
+
+#[tokio::test]
+async fn range_reads_return_requested_chunks() {
+    let harness = Harness::start().await;
+    let chunk_size = 1024;
+    let data = test_data(100 * chunk_size, 3);
+    harness.put("ranged", &data).await;
+
+    for (start, end) in [(0, Some(100)), (3, Some(7)), (99, None), (42, None)] {
+        let stream = download::concurrent_stream_vecs_from(
+            harness.client.clone(),
+            BUCKET.to_string(),
+            "ranged".to_string(),
+            start,
+            end,
+            chunk_size,
+        )
+        .await;
+        let chunks: Vec<Bytes> = stream.map(|c| c.unwrap()).collect().await;
+        let expected = &data[start * chunk_size..end.unwrap_or(100) * chunk_size];
+        assert_eq!(chunks.concat(), expected);
+    }
+}
+
+#[tokio::test]
+async fn missing_objects_are_not_found() {
+    let harness = Harness::start().await;
+
+    assert!(
+        download::try_download_vec::<u8>(&harness.client, BUCKET, "missing")
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(download::try_head(&harness.client, BUCKET, "missing")
+        .await
+        .unwrap()
+        .is_none());
+    let mut stream = pin!(
+        download::stream_vecs_from(
+            harness.client.clone(),
+            BUCKET.to_string(),
+            "missing".to_string(),
+            0,
+            None,
+            16,
+        )
+        .await
+    );
+    assert!(matches!(
+        stream.next().await,
+        Some(Err(VecStreamError::NotFound(_)))
+    ));
+    assert!(stream.next().await.is_none());
+}
+
+/// Counts the GET requests a client sends, retries included.
+#[derive(Debug, Default)]
+struct GetCounter(Arc<AtomicUsize>);
+
+impl Intercept for GetCounter {
+    fn name(&self) -> &'static str {
+        "GetCounter"
+    }
+
+    fn read_before_transmit(
+        &self,
+        context: &BeforeTransmitInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        _cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        if context.request().method() == "GET" {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn stream_resumes_after_mid_stream_failure() {
+    let harness = Harness::start().await;
+    let chunk_size = 256 << 10;
+    let count = 256;
+    let data = test_data(count * chunk_size, 4);
+    harness.put("interrupted", &data).await;
+
+    let gets = Arc::new(AtomicUsize::new(0));
+    let client = Arc::new(Client::from_conf(
+        harness
+            .client
+            .config()
+            .to_builder()
+            .interceptor(GetCounter(gets.clone()))
+            .build(),
+    ));
+    // retry the range request until the container is back
+    let options = OpOptions {
+        retry: Some(RetryConfig::standard().with_max_attempts(10)),
+        ..OpOptions::default()
+    };
+    let mut stream = pin!(
+        download::stream_vecs_from_with_options(
+            client,
+            BUCKET.to_string(),
+            "interrupted".to_string(),
+            0,
+            Some(count),
+            chunk_size,
+            options,
+        )
+        .await
+    );
+
+    let mut received = stream.next().await.unwrap().unwrap().to_vec();
+    harness.container.pause().await.unwrap();
+    let read = async {
+        while let Some(chunk) = stream.next().await {
+            received.extend_from_slice(&chunk.unwrap());
+        }
+    };
+    let unpause = async {
+        tokio::time::sleep(Duration::from_secs(4)).await;
+        harness.container.unpause().await.unwrap();
+    };
+    tokio::join!(read, unpause);
+
+    assert_eq!(received, data);
+    // the body failed while paused, so the rest came from another range request
+    assert!(gets.load(Ordering::SeqCst) >= 2);
+}
+
+#[tokio::test]
+async fn session_resumes_from_persisted_cursor() {
+    let harness = Harness::start().await;
+    let chunk_size = 1024;
+    let objects: Vec<Vec<u8>> = (0..3).map(|i| test_data(4 * chunk_size, i)).collect();
+    for (index, data) in objects.iter().enumerate() {
+        harness.put(&format!("session/{index}"), data).await;
+    }
+
+    let mut session = DownloadSession::from_prefix(
+        harness.client.clone(),
+        BUCKET.to_string(),
+        "session/",
+        chunk_size,
+    )
+    .await
+    .unwrap();
+    let mut received = vec![Vec::new(); objects.len()];
+    for _ in 0..6 {
+        let chunk = session.next().await.unwrap().unwrap();
+        received[chunk.key_index].extend_from_slice(&chunk.data);
+    }
+
+    // simulate a crash after the sixth chunk
+    let persisted = serde_json::to_string(session.cursor()).unwrap();
+    drop(session);
+
+    let cursor: DownloadCursor = serde_json::from_str(&persisted).unwrap();
+    let mut session = DownloadSession::resume(harness.client.clone(), cursor).unwrap();
+    while let Some(chunk) = session.next().await {
+        let chunk = chunk.unwrap();
+        received[chunk.key_index].extend_from_slice(&chunk.data);
+    }
+
+    assert!(session.cursor().is_done());
+    assert_eq!(received, objects);
+}
+
+#[tokio::test]
+async fn session_rejects_partial_chunks() {
+    let harness = Harness::start().await;
+    harness.put("partial/small", &test_data(10, 5)).await;
+
+    let result =
+        DownloadSession::from_prefix(harness.client.clone(), BUCKET.to_string(), "partial/", 1024)
+            .await;
+    assert!(matches!(result, Err(SessionError::UnalignedSize { .. })));
+}
+
+#[tokio::test]
+async fn compressed_objects_round_trip_through_a_scope() {
+    let harness = Harness::start().await;
+    let store = S3Store::new(harness.client.clone(), BucketName::new(BUCKET).unwrap());
+    let scope = ScopedStore::new(store, "tenant").unwrap();
+    let samples: Vec<Vec<u8>> = (0..1000)
+        .map(|i| format!("{{\"id\":{i},\"name\":\"record {i}\"}}").into_bytes())
+        .collect();
+    let dictionary = Dictionary::new(compress::train_dictionary(&samples, 1024).unwrap());
+
+    let dictionaries = scope.dictionaries("dictionaries/").unwrap();
+    dictionaries.save(&dictionary).await.unwrap();
+    scope
+        .put_compressed("record", &samples[3], Some(&dictionary), 3)
+        .await
+        .unwrap();
+
+    // a fresh store, so the dictionary is fetched rather than cached
+    let dictionaries = scope.dictionaries("dictionaries/").unwrap();
+    let data = scope
+        .get_decompressed("record", &dictionaries)
+        .await
+        .unwrap();
+    assert_eq!(data, samples[3]);
+    assert!(harness
+        .client
+        .head_object()
+        .bucket(BUCKET)
+        .key(format!("tenant/dictionaries/{}", dictionary.id))
+        .send()
+        .await
+        .is_ok());
+}
+
+#[tokio::test]
+async fn tampered_dictionaries_are_rejected() {
+    let harness = Harness::start().await;
+    let store = S3Store::new(harness.client.clone(), BucketName::new(BUCKET).unwrap());
+    let dictionary = Dictionary::new(b"the real dictionary".to_vec());
+    harness
+        .put(
+            &format!("dictionaries/{}", dictionary.id),
+            b"something else",
+        )
+        .await;
+
+    let result = store
+        .dictionaries("dictionaries/")
+        .fetch(&dictionary.id)
+        .await;
+    assert!(matches!(
+        result,
+        Err(CompressionError::DictionaryMismatch { expected, .. }) if expected == dictionary.id
+    ));
+}
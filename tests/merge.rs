@@ -0,0 +1,115 @@
+use bytes::Bytes;
+use futures::stream::{self, StreamExt};
+use futures::Stream;
+use vl_aws_util::merge::{merge_by_index, tag_indices};
+
+/// A shard of chunks that are just their global index, followed by an
+/// error if `error` is given.
+fn shard(
+    start_index: usize,
+    count: usize,
+    stride: usize,
+    error: Option<&'static str>,
+) -> impl Stream<Item = Result<(usize, Bytes), &'static str>> {
+    let chunks = (0..count)
+        .map(|ix| Ok(Bytes::from((start_index + ix * stride).to_string())))
+        .chain(error.map(Err));
+
+    tag_indices(
+        stream::iter(chunks.collect::<Vec<_>>()),
+        start_index,
+        stride,
+    )
+}
+
+async fn merge(
+    shards: Vec<impl Stream<Item = Result<(usize, Bytes), &'static str>> + Send + 'static>,
+) -> Vec<Result<(usize, Bytes), &'static str>> {
+    merge_by_index(shards, 2).collect().await
+}
+
+fn indices(merged: &[Result<(usize, Bytes), &'static str>]) -> Vec<usize> {
+    merged
+        .iter()
+        .map(|item| {
+            let (index, chunk) = item.as_ref().unwrap();
+            // every chunk must still be tagged with its own index
+            assert_eq!(chunk, &Bytes::from(index.to_string()));
+            *index
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn tags_follow_the_stride() {
+    let tagged: Vec<_> = shard(2, 4, 3, None)
+        .map(|item| item.unwrap().0)
+        .collect()
+        .await;
+
+    assert_eq!(tagged, vec![2, 5, 8, 11]);
+}
+
+#[tokio::test]
+async fn round_robin_shards_merge_in_index_order() {
+    let merged = merge(vec![
+        shard(0, 4, 3, None),
+        shard(1, 3, 3, None),
+        shard(2, 3, 3, None),
+    ])
+    .await;
+
+    assert_eq!(indices(&merged), (0..10).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn contiguous_shards_merge_in_index_order() {
+    // listed out of order, which shouldn't matter
+    let merged = merge(vec![
+        shard(6, 2, 1, None),
+        shard(0, 3, 1, None),
+        shard(3, 3, 1, None),
+    ])
+    .await;
+
+    assert_eq!(indices(&merged), (0..8).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn empty_shards_are_skipped() {
+    let merged = merge(vec![
+        shard(0, 0, 2, None),
+        shard(0, 3, 2, None),
+        shard(1, 3, 2, None),
+        shard(7, 0, 2, None),
+    ])
+    .await;
+    assert_eq!(indices(&merged), (0..6).collect::<Vec<_>>());
+
+    assert!(merge(vec![shard(0, 0, 1, None)]).await.is_empty());
+    assert!(merge(Vec::<stream::Empty<_>>::new()).await.is_empty());
+}
+
+#[tokio::test]
+async fn merging_ends_at_the_first_error() {
+    let merged = merge(vec![
+        shard(0, 3, 2, None),
+        shard(1, 1, 2, Some("shard failed")),
+    ])
+    .await;
+
+    assert_eq!(merged.len(), 3);
+    assert_eq!(indices(&merged[..2]), vec![0, 1]);
+    assert_eq!(merged[2], Err("shard failed"));
+}
+
+#[tokio::test]
+async fn errors_before_the_first_chunk_end_the_merge() {
+    let merged = merge(vec![
+        shard(0, 3, 2, None),
+        shard(1, 0, 2, Some("shard failed")),
+    ])
+    .await;
+
+    assert_eq!(merged, vec![Err("shard failed")]);
+}
@@ -0,0 +1,77 @@
+use vl_aws_util::migrate::{migrate_blob, migrate_upload_info, MigrateError};
+use vl_aws_util::upload::{PartId, UploadInfo};
+
+/// An `UploadInfo` as serialized before it was versioned.
+const LEGACY: &str = r#"{
+    "bucket": "bucket",
+    "key": "key",
+    "size_per_upload": 5242880,
+    "upload_id": "upload",
+    "parts": ["\"etag1\"", "\"etag2\""],
+    "uploaded_bytes": 10485760
+}"#;
+
+#[test]
+fn legacy_info_is_upgraded() {
+    let info = migrate_upload_info(LEGACY).unwrap();
+    assert_eq!(info.version, 1);
+    assert_eq!(info.upload_id, "upload");
+    assert_eq!(
+        info.parts,
+        vec![
+            PartId::ETag("\"etag1\"".to_string()),
+            PartId::ETag("\"etag2\"".to_string())
+        ]
+    );
+    assert_eq!(info.created_at, None);
+
+    let json = serde_json::to_string(&info).unwrap();
+    let upload: UploadInfo = serde_json::from_str(&json).unwrap();
+    assert_eq!(upload.upload_id(), "upload");
+}
+
+#[test]
+fn migration_is_idempotent() {
+    let once = migrate_blob(LEGACY).unwrap().unwrap();
+    assert_eq!(migrate_blob(&once).unwrap(), None);
+
+    let multi = format!(r#"{{"uploads": [{LEGACY}, {once}]}}"#);
+    let migrated = migrate_blob(&multi).unwrap().unwrap();
+    assert_eq!(migrate_blob(&migrated).unwrap(), None);
+}
+
+#[test]
+fn unknown_versions_are_rejected() {
+    let future = LEGACY.replacen('{', r#"{"version": 2,"#, 1);
+    assert!(matches!(
+        migrate_upload_info(&future),
+        Err(MigrateError::UnsupportedVersion(_))
+    ));
+}
+
+#[test]
+fn legacy_info_deserializes_directly() {
+    let upload: UploadInfo = serde_json::from_str(LEGACY).unwrap();
+    assert_eq!(upload.upload_id(), "upload");
+    assert_eq!(upload.key(), "key");
+    assert_eq!(upload.age(), None);
+
+    let future = LEGACY.replacen('{', r#"{"version": 2,"#, 1);
+    assert!(serde_json::from_str::<UploadInfo>(&future).is_err());
+}
+
+#[test]
+fn info_deserializes_from_other_formats() {
+    let info = migrate_upload_info(LEGACY).unwrap();
+    let mut cbor = Vec::new();
+    ciborium::into_writer(&info, &mut cbor).unwrap();
+    let upload: UploadInfo = ciborium::from_reader(cbor.as_slice()).unwrap();
+    assert_eq!(upload.upload_id(), "upload");
+
+    let legacy: serde_json::Value = serde_json::from_str(LEGACY).unwrap();
+    let mut cbor = Vec::new();
+    ciborium::into_writer(&legacy, &mut cbor).unwrap();
+    let upload: UploadInfo = ciborium::from_reader(cbor.as_slice()).unwrap();
+    assert_eq!(upload.key(), "key");
+    assert_eq!(upload.age(), None);
+}
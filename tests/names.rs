@@ -0,0 +1,163 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use aws_sdk_s3::config::Credentials;
+use bytes::Bytes;
+use futures::StreamExt;
+use vl_aws_util::download::{self, DownloadVecError, HeadError, VecStreamError};
+use vl_aws_util::keymap::{HmacKeyMapper, KeyMapping};
+use vl_aws_util::names::{BucketName, NameError, ObjectKey};
+use vl_aws_util::presign::{self, PostConditions, PresignError};
+use vl_aws_util::store::{S3Store, StoreCopyError, StoreDeleteError, StorePutError};
+use vl_aws_util::upload::{Upload, UploadCreateError};
+
+#[test]
+fn valid_bucket_names_are_accepted() {
+    for name in ["abc", "my-bucket", "my.bucket", "1bucket", &"a".repeat(63)] {
+        assert!(BucketName::new(name).is_ok(), "{name:?}");
+    }
+}
+
+#[test]
+fn bucket_names_must_have_a_valid_length() {
+    for name in ["", "ab", &"a".repeat(64)] {
+        assert!(
+            matches!(BucketName::new(name), Err(NameError::BucketLength(_))),
+            "{name:?}"
+        );
+    }
+}
+
+#[test]
+fn bucket_names_must_use_valid_characters() {
+    for name in ["MyBucket", "my_bucket", "my bucket", "bücket"] {
+        assert!(
+            matches!(BucketName::new(name), Err(NameError::BucketCharacters(_))),
+            "{name:?}"
+        );
+    }
+}
+
+#[test]
+fn bucket_names_must_start_and_end_with_a_letter_or_digit() {
+    for name in ["-bucket", "bucket-", ".bucket", "bucket."] {
+        assert!(
+            matches!(BucketName::new(name), Err(NameError::BucketEdges(_))),
+            "{name:?}"
+        );
+    }
+}
+
+#[test]
+fn bucket_names_must_not_contain_adjacent_periods() {
+    assert_eq!(
+        BucketName::new("my..bucket"),
+        Err(NameError::BucketAdjacentPeriods("my..bucket".to_string()))
+    );
+}
+
+#[test]
+fn bucket_names_must_not_be_ip_addresses() {
+    assert_eq!(
+        BucketName::new("192.168.5.4"),
+        Err(NameError::BucketIpAddress("192.168.5.4".to_string()))
+    );
+    assert!(BucketName::new("192.168.5.4.5").is_ok());
+}
+
+#[test]
+fn keys_are_validated() {
+    assert!(ObjectKey::new("a/b/c.txt").is_ok());
+    assert!(ObjectKey::new("k".repeat(1024)).is_ok());
+    assert_eq!(ObjectKey::new(""), Err(NameError::EmptyKey));
+    assert!(matches!(
+        ObjectKey::new("k".repeat(1025)),
+        Err(NameError::KeyLength(_))
+    ));
+    assert!(matches!(
+        ObjectKey::new("a\nb"),
+        Err(NameError::KeyControlCharacters(_))
+    ));
+}
+
+#[test]
+fn stores_reject_invalid_bucket_names() {
+    assert!(matches!(
+        S3Store::try_new(common::client(), "My_Bucket"),
+        Err(NameError::BucketCharacters(_))
+    ));
+}
+
+#[tokio::test]
+async fn store_operations_reject_invalid_keys() {
+    let store = common::store();
+
+    assert!(matches!(
+        store.download_vec::<u8>("").await,
+        Err(DownloadVecError::InvalidKey(NameError::EmptyKey))
+    ));
+    assert!(matches!(
+        store.head("a\0b").await,
+        Err(HeadError::InvalidKey(_))
+    ));
+    assert!(matches!(
+        store.delete_object("").await,
+        Err(StoreDeleteError::InvalidKey(_))
+    ));
+    assert!(matches!(
+        store.upload("").await,
+        Err(UploadCreateError::InvalidKey(_))
+    ));
+    assert!(matches!(
+        store.copy_object("", "key").await,
+        Err(StoreCopyError::InvalidKey(_))
+    ));
+
+    let mut stream = Box::pin(store.stream_vecs_from("", 0, None, 8).await);
+    assert!(matches!(
+        stream.next().await,
+        Some(Err(VecStreamError::InvalidKey(_)))
+    ));
+    assert!(stream.next().await.is_none());
+}
+
+#[tokio::test]
+async fn mapped_keys_are_validated() {
+    let mapping = KeyMapping::new(Arc::new(HmacKeyMapper::new("secret")));
+    let store = common::store().with_key_mapping(mapping);
+    // valid as given, but every segment maps to 32 characters
+    let key = "a/".repeat(400);
+    assert!(ObjectKey::new(key.as_str()).is_ok());
+
+    assert!(matches!(
+        store.put_object(key, Bytes::from_static(b"data")).await,
+        Err(StorePutError::InvalidKey(NameError::KeyLength(_)))
+    ));
+}
+
+#[tokio::test]
+async fn free_functions_reject_invalid_keys() {
+    let client = common::client();
+
+    assert!(matches!(
+        download::download_vec::<u8>(&client, "bucket", "").await,
+        Err(DownloadVecError::InvalidKey(NameError::EmptyKey))
+    ));
+    assert!(matches!(
+        download::try_head(&client, "bucket", "a\0b").await,
+        Err(HeadError::InvalidKey(_))
+    ));
+    assert!(matches!(
+        Upload::new(client.clone(), "bucket".to_string(), String::new()).await,
+        Err(UploadCreateError::InvalidKey(_))
+    ));
+
+    let credentials = Credentials::new("test", "test", None, None, "test");
+    let conditions = PostConditions::new(Duration::from_secs(60));
+    assert!(matches!(
+        presign::presign_post(&client, &credentials, "bucket", "a\nb/", &conditions).await,
+        Err(PresignError::InvalidKey(NameError::KeyControlCharacters(_)))
+    ));
+}
@@ -0,0 +1,219 @@
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+use bytes::Bytes;
+use vl_aws_util::pipeline::{BufferedPipeline, PipelineConfig};
+
+fn config(dir: &Path) -> PipelineConfig {
+    PipelineConfig {
+        dir: dir.to_path_buf(),
+        segment_size: 1 << 20,
+        max_bytes: 1 << 30,
+    }
+}
+
+fn record(i: u64) -> Vec<u8> {
+    i.to_le_bytes().to_vec()
+}
+
+fn segments(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.ends_with(".seg"))
+        .collect();
+    names.sort();
+    names
+}
+
+#[tokio::test]
+async fn records_arrive_in_order() {
+    let dir = tempfile::tempdir().unwrap();
+    let (mut writer, mut consumer) = BufferedPipeline::open(config(dir.path()))
+        .await
+        .unwrap()
+        .split();
+
+    for i in 0..10 {
+        writer.push(&record(i)).await.unwrap();
+    }
+    writer.finish().await.unwrap();
+
+    for i in 0..10 {
+        assert_eq!(consumer.next().await.unwrap().unwrap(), record(i));
+    }
+    assert!(consumer.next().await.is_none());
+}
+
+#[tokio::test]
+async fn torn_records_are_truncated_on_reopen() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let (mut writer, _) = BufferedPipeline::open(config(dir.path()))
+            .await
+            .unwrap()
+            .split();
+        for i in 0..3 {
+            writer.push(&record(i)).await.unwrap();
+        }
+    }
+
+    // a crash in the middle of a write leaves a header and part of a record
+    let segment = dir.path().join(&segments(dir.path())[0]);
+    let intact_len = std::fs::metadata(&segment).unwrap().len();
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&segment)
+        .unwrap();
+    file.write_all(&100u64.to_le_bytes()).unwrap();
+    file.write_all(&[1; 10]).unwrap();
+    drop(file);
+
+    let pipeline = BufferedPipeline::open(config(dir.path())).await.unwrap();
+    assert_eq!(pipeline.written(), 3);
+    assert_eq!(std::fs::metadata(&segment).unwrap().len(), intact_len);
+
+    let (mut writer, mut consumer) = pipeline.split();
+    writer.push(&record(3)).await.unwrap();
+    writer.finish().await.unwrap();
+    for i in 0..4 {
+        assert_eq!(consumer.next().await.unwrap().unwrap(), record(i));
+    }
+    assert!(consumer.next().await.is_none());
+}
+
+#[tokio::test]
+async fn a_torn_header_is_truncated_on_reopen() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let (mut writer, _) = BufferedPipeline::open(config(dir.path()))
+            .await
+            .unwrap()
+            .split();
+        writer.push(&record(0)).await.unwrap();
+    }
+    let segment = dir.path().join(&segments(dir.path())[0]);
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&segment)
+        .unwrap();
+    file.write_all(&[8, 0, 0]).unwrap();
+    drop(file);
+
+    let pipeline = BufferedPipeline::open(config(dir.path())).await.unwrap();
+    assert_eq!(pipeline.written(), 1);
+}
+
+#[tokio::test]
+async fn reading_resumes_from_the_last_commit() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let (mut writer, mut consumer) = BufferedPipeline::open(config(dir.path()))
+            .await
+            .unwrap()
+            .split();
+        for i in 0..5 {
+            writer.push(&record(i)).await.unwrap();
+        }
+        writer.finish().await.unwrap();
+
+        consumer.next().await.unwrap().unwrap();
+        consumer.next().await.unwrap().unwrap();
+        consumer.commit().await.unwrap();
+        // read but never committed, so read again after the restart
+        consumer.next().await.unwrap().unwrap();
+    }
+
+    let pipeline = BufferedPipeline::open(config(dir.path())).await.unwrap();
+    assert_eq!(pipeline.written(), 5);
+    let (writer, mut consumer) = pipeline.split();
+    assert!(writer.is_finished());
+    assert_eq!(consumer.position(), 2);
+    for i in 2..5 {
+        assert_eq!(consumer.next().await.unwrap().unwrap(), record(i));
+    }
+    assert!(consumer.next().await.is_none());
+}
+
+#[tokio::test]
+async fn committed_segments_are_removed() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = config(dir.path());
+    // every record gets its own segment
+    config.segment_size = 1;
+    let (mut writer, mut consumer) = BufferedPipeline::open(config).await.unwrap().split();
+    for i in 0..4 {
+        writer.push(&record(i)).await.unwrap();
+    }
+    assert_eq!(segments(dir.path()).len(), 4);
+
+    consumer.next().await.unwrap().unwrap();
+    consumer.next().await.unwrap().unwrap();
+    assert_eq!(segments(dir.path()).len(), 4);
+    consumer.commit().await.unwrap();
+    assert_eq!(segments(dir.path()).len(), 2);
+}
+
+#[tokio::test]
+async fn push_waits_for_space() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = config(dir.path());
+    config.segment_size = 1;
+    // room for two records with their headers
+    config.max_bytes = 32;
+    let (mut writer, mut consumer) = BufferedPipeline::open(config).await.unwrap().split();
+    writer.push(&record(0)).await.unwrap();
+    writer.push(&record(1)).await.unwrap();
+
+    let blocked = tokio::time::timeout(Duration::from_millis(100), writer.push(&record(2))).await;
+    assert!(blocked.is_err(), "push should wait while the queue is full");
+
+    // reading alone frees nothing, committing does
+    consumer.next().await.unwrap().unwrap();
+    let push = tokio::spawn(async move {
+        writer.push(&record(2)).await.unwrap();
+        writer
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!push.is_finished());
+    consumer.commit().await.unwrap();
+    let writer = tokio::time::timeout(Duration::from_secs(5), push)
+        .await
+        .expect("push should continue after a commit")
+        .unwrap();
+    writer.finish().await.unwrap();
+
+    for i in 1..3 {
+        assert_eq!(consumer.next().await.unwrap().unwrap(), record(i));
+    }
+    assert!(consumer.next().await.is_none());
+}
+
+#[tokio::test]
+async fn push_does_not_wait_once_everything_is_read() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = config(dir.path());
+    config.max_bytes = 1;
+    let (mut writer, mut consumer) = BufferedPipeline::open(config).await.unwrap().split();
+    for i in 0..3 {
+        writer.push(&record(i)).await.unwrap();
+        assert_eq!(consumer.next().await.unwrap().unwrap(), record(i));
+    }
+}
+
+#[tokio::test]
+async fn drain_pushes_a_whole_stream() {
+    let dir = tempfile::tempdir().unwrap();
+    let (writer, mut consumer) = BufferedPipeline::open(config(dir.path()))
+        .await
+        .unwrap()
+        .split();
+    let source = futures::stream::iter((0..5).map(|i| Ok::<_, ()>(Bytes::from(record(i)))));
+    writer.drain(source).await.unwrap();
+
+    for i in 0..5 {
+        assert_eq!(consumer.next().await.unwrap().unwrap(), record(i));
+    }
+    assert!(consumer.next().await.is_none());
+}
@@ -0,0 +1,222 @@
+mod common;
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+use std::time::Duration;
+
+use aws_sdk_s3::{primitives::SdkBody, Client};
+use bytes::Bytes;
+use vl_aws_util::gc::GcOptions;
+use vl_aws_util::keymap::{HmacKeyMapper, KeyMapping};
+use vl_aws_util::quota::{Quota, QuotaState};
+use vl_aws_util::store::{S3Store, StoreCopyError, StorePutError};
+
+fn quota() -> Quota {
+    Quota::new([("a/".to_string(), 100), ("a/b/".to_string(), 10)])
+}
+
+#[test]
+fn charges_count_against_every_matching_prefix() {
+    let quota = quota();
+    quota.charge("a/b/c", 5).unwrap();
+    quota.charge("a/c", 20).unwrap();
+    quota.charge("other", 1000).unwrap();
+
+    assert_eq!(quota.usage("a/"), 25);
+    assert_eq!(quota.usage("a/b/"), 5);
+    assert_eq!(quota.usage("other"), 0);
+}
+
+#[test]
+fn exceeding_charges_charge_nothing() {
+    let quota = quota();
+    quota.charge("a/b/c", 8).unwrap();
+
+    let exceeded = quota.charge("a/b/c", 3).unwrap_err();
+    assert_eq!(exceeded.prefix, "a/b/");
+    assert_eq!(exceeded.limit, 10);
+    assert_eq!(exceeded.used, 8);
+    assert_eq!(exceeded.requested, 3);
+    // the outer prefix had room, but wasn't charged either
+    assert_eq!(quota.usage("a/"), 8);
+
+    quota.charge("a/b/c", 2).unwrap();
+    assert_eq!(quota.usage("a/b/"), 10);
+}
+
+#[test]
+fn releases_make_room_again() {
+    let quota = quota();
+    quota.charge("a/b/c", 10).unwrap();
+    assert!(quota.charge("a/b/d", 1).is_err());
+
+    quota.release("a/b/c", 4);
+    assert_eq!(quota.usage("a/b/"), 6);
+    assert_eq!(quota.usage("a/"), 6);
+    quota.charge("a/b/d", 4).unwrap();
+}
+
+#[test]
+fn releases_never_go_below_zero() {
+    let quota = quota();
+    quota.charge("a/c", 5).unwrap();
+    quota.release("a/c", 50);
+    quota.release("a/b/c", 50);
+
+    assert_eq!(quota.usage("a/"), 0);
+    assert_eq!(quota.usage("a/b/"), 0);
+}
+
+#[test]
+fn state_round_trips() {
+    let quota = quota();
+    quota.charge("a/b/c", 7).unwrap();
+
+    let json = serde_json::to_string(&quota.state()).unwrap();
+    let state: QuotaState = serde_json::from_str(&json).unwrap();
+    let restored = Quota::from_state([("a/b/".to_string(), 10)], state);
+    assert_eq!(restored.usage("a/b/"), 7);
+    assert!(restored.charge("a/b/c", 4).is_err());
+}
+
+#[tokio::test]
+async fn store_writes_over_the_limit_are_rejected() {
+    let (client, requests) = common::responding_client(|_| {
+        http::Response::builder()
+            .status(200)
+            .body(SdkBody::empty())
+            .unwrap()
+    });
+    let quota = Arc::new(quota());
+    let store = S3Store::try_new(client, "bucket")
+        .unwrap()
+        .with_quota(quota.clone());
+
+    let result = store
+        .put_object("a/b/c", Bytes::from_static(&[0; 11]))
+        .await;
+    assert!(matches!(result, Err(StorePutError::QuotaExceeded(_))));
+    assert_eq!(quota.usage("a/"), 0);
+    assert_eq!(requests.load(Ordering::SeqCst), 0);
+}
+
+/// A client reporting every object as `size` bytes long.
+fn sized_objects(size: usize) -> (Arc<Client>, Arc<AtomicUsize>) {
+    common::responding_client(move |request| {
+        let response = http::Response::builder().status(200);
+        if request.method() == http::Method::HEAD {
+            response.header("content-length", size.to_string())
+        } else {
+            response
+        }
+        .body(SdkBody::empty())
+        .unwrap()
+    })
+}
+
+#[tokio::test]
+async fn copies_are_charged_the_source_size() {
+    let (client, requests) = sized_objects(20);
+    let quota = Arc::new(quota());
+    let store = S3Store::try_new(client, "bucket")
+        .unwrap()
+        .with_quota(quota.clone());
+
+    let result = store.copy_object("source", "a/b/c").await;
+    assert!(matches!(result, Err(StoreCopyError::QuotaExceeded(_))));
+    assert_eq!(quota.usage("a/"), 0);
+    // only the source was looked up
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn copies_release_the_object_they_replace() {
+    let (client, _) = sized_objects(20);
+    let quota = Arc::new(quota());
+    // the destination already exists, and was charged when written
+    quota.charge("a/c", 20).unwrap();
+    let store = S3Store::try_new(client, "bucket")
+        .unwrap()
+        .with_quota(quota.clone());
+
+    store.copy_object("source", "a/c").await.unwrap();
+    assert_eq!(quota.usage("a/"), 20);
+}
+
+/// A store with a key sidecar under `keys/`, whose client reports every
+/// object as 3 bytes long, lists `m/old` as the only object and logs every
+/// request by method and path.
+fn sidecar_store(quota: Arc<Quota>) -> (S3Store, Arc<Mutex<Vec<String>>>) {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let logged = log.clone();
+    let (client, _) = common::responding_client(move |request| {
+        let uri = request.uri();
+        logged
+            .lock()
+            .unwrap()
+            .push(format!("{} {}", request.method(), uri.path()));
+        let response = http::Response::builder().status(200);
+        let body = match *request.method() {
+            http::Method::HEAD => {
+                return response
+                    .header("content-length", "3")
+                    .body(SdkBody::empty())
+                    .unwrap()
+            }
+            http::Method::GET if uri.query().is_some_and(|q| q.contains("list-type=2")) => {
+                "<ListBucketResult><Contents><Key>m/old</Key>\
+                 <LastModified>2020-01-01T00:00:00.000Z</LastModified>\
+                 <Size>3</Size></Contents></ListBucketResult>"
+            }
+            http::Method::POST => "<DeleteResult/>",
+            _ => "",
+        };
+        response.body(SdkBody::from(body)).unwrap()
+    });
+    let mapping = KeyMapping::new(Arc::new(HmacKeyMapper::new("secret"))).with_sidecar("keys/");
+    let store = S3Store::try_new(client, "bucket")
+        .unwrap()
+        .with_key_mapping(mapping)
+        .with_quota(quota);
+
+    (store, log)
+}
+
+#[tokio::test]
+async fn deletes_remove_the_key_sidecar_entry() {
+    let quota = Arc::new(Quota::new([("keys/".to_string(), 100)]));
+    let (store, log) = sidecar_store(quota.clone());
+    let stored_key = store.stored_key("a/c");
+    // the sidecar entry holds the original key, and was charged when written
+    quota.charge(&format!("keys/{stored_key}"), 3).unwrap();
+
+    store.delete_object("a/c").await.unwrap();
+    assert_eq!(quota.usage("keys/"), 0);
+    assert_eq!(
+        log.lock().unwrap().last().unwrap(),
+        &format!("DELETE /bucket/keys/{stored_key}")
+    );
+}
+
+#[tokio::test]
+async fn garbage_collection_removes_key_sidecar_entries() {
+    let quota = Arc::new(Quota::new([("keys/".to_string(), 100)]));
+    quota.charge("keys/m/old", 3).unwrap();
+    let (store, log) = sidecar_store(quota.clone());
+    let options = GcOptions {
+        dry_run: false,
+        min_age: Duration::from_secs(60),
+        ..Default::default()
+    };
+
+    let report = store.gc_prefix("m/", &[], options).await.unwrap();
+    assert_eq!(report.deleted, ["m/old"]);
+    assert!(report.failed.is_empty());
+    assert_eq!(quota.usage("keys/"), 0);
+    assert_eq!(
+        log.lock().unwrap().last().unwrap(),
+        "DELETE /bucket/keys/m/old"
+    );
+}
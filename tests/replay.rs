@@ -0,0 +1,118 @@
+#![cfg(feature = "replay")]
+
+mod common;
+
+use aws_sdk_s3::{config::Credentials, Client};
+use aws_smithy_http_client::Connector;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+use vl_aws_util::{client::ClientBuilder, replay::Recorder};
+
+const BODY: &str = "recorded body";
+
+/// Answers every request on a local port with `BODY`.
+async fn serve() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut request = Vec::new();
+                let mut buf = [0; 4096];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    if n == 0 {
+                        return;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{BODY}",
+                    BODY.len()
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+            });
+        }
+    });
+
+    port
+}
+
+async fn record(path: &std::path::Path, recorder: Recorder) {
+    let port = serve().await;
+    let config = common::config()
+        .endpoint_url(format!("http://127.0.0.1:{port}"))
+        .force_path_style(true)
+        .credentials_provider(Credentials::new(
+            "AKIDEXAMPLE",
+            "secret",
+            Some("session-token".to_string()),
+            None,
+            "test",
+        ))
+        .http_client(recorder.clone())
+        .build();
+    let client = Client::from_conf(config);
+    let object = client
+        .get_object()
+        .bucket("bucket")
+        .key("key")
+        .send()
+        .await
+        .unwrap();
+    let data = object.body.collect().await.unwrap().into_bytes();
+    assert_eq!(data, BODY.as_bytes());
+    recorder.finish().unwrap();
+    assert!(path.exists());
+}
+
+fn recorder(path: &std::path::Path) -> Recorder {
+    Recorder::with_connector(path, Connector::builder().build_http()).unwrap()
+}
+
+#[tokio::test]
+async fn recordings_redact_credentials() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("recording.jsonl");
+    record(&path, recorder(&path)).await;
+
+    let recording = std::fs::read_to_string(&path).unwrap();
+    assert!(recording.contains("\"authorization\":[\"<redacted>\"]"));
+    assert!(recording.contains("\"x-amz-security-token\":[\"<redacted>\"]"));
+    assert!(!recording.contains("AWS4-HMAC-SHA256"));
+    assert!(!recording.contains("session-token"));
+    assert!(recording.contains(BODY));
+}
+
+#[tokio::test]
+async fn recordings_can_leave_out_bodies() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("recording.jsonl");
+    record(&path, recorder(&path).without_bodies()).await;
+
+    let recording = std::fs::read_to_string(&path).unwrap();
+    assert!(!recording.contains(BODY));
+    assert!(!recording.contains("\"Data\""));
+    assert!(recording.contains("\"Eof\""));
+}
+
+#[tokio::test]
+async fn recordings_replay_offline() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("recording.jsonl");
+    record(&path, recorder(&path)).await;
+
+    let client = ClientBuilder::new().replay(&path).unwrap().build().await;
+    let object = client
+        .get_object()
+        .bucket("bucket")
+        .key("key")
+        .send()
+        .await
+        .unwrap();
+    let data = object.body.collect().await.unwrap().into_bytes();
+    assert_eq!(data, BODY.as_bytes());
+}
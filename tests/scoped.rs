@@ -0,0 +1,136 @@
+mod common;
+
+use vl_aws_util::scoped::{validate_fragment, ScopeError, ScopedStore};
+
+fn scope() -> ScopedStore {
+    ScopedStore::new(common::store(), "tenant").unwrap()
+}
+
+#[test]
+fn plain_fragments_are_accepted() {
+    for fragment in [
+        "a", "a/b", "a\\b", "a/b/", "a\\b\\", "a.b", "..a", "a..", "...",
+    ] {
+        assert!(validate_fragment(fragment).is_ok(), "{fragment:?}");
+    }
+}
+
+#[test]
+fn empty_fragments_are_rejected() {
+    assert!(matches!(validate_fragment(""), Err(ScopeError::EmptyKey)));
+}
+
+#[test]
+fn absolute_fragments_are_rejected() {
+    for fragment in ["/", "/a", "\\a", "\\\\server\\a"] {
+        assert!(
+            matches!(validate_fragment(fragment), Err(ScopeError::AbsoluteKey(_))),
+            "{fragment:?}"
+        );
+    }
+}
+
+#[test]
+fn traversal_segments_are_rejected() {
+    for fragment in [
+        ".", "..", "./a", "../a", "a/..", "a/../b", "a/./b", "a/../", "a\\..", "a\\..\\b",
+        "a/..\\b", "a\\../b", "a/..\\",
+    ] {
+        assert!(
+            matches!(
+                validate_fragment(fragment),
+                Err(ScopeError::PathTraversal(_))
+            ),
+            "{fragment:?}"
+        );
+    }
+}
+
+#[test]
+fn empty_segments_are_rejected() {
+    for fragment in ["a//b", "a\\\\b", "a/\\b", "a//", "a/b//", "a\\/"] {
+        assert!(
+            matches!(
+                validate_fragment(fragment),
+                Err(ScopeError::EmptySegment(_))
+            ),
+            "{fragment:?}"
+        );
+    }
+}
+
+#[test]
+fn keys_are_joined_to_the_prefix() {
+    let scope = scope();
+    assert_eq!(scope.prefix(), "tenant/");
+    assert_eq!(scope.key("a/b").unwrap(), "tenant/a/b");
+    assert_eq!(scope.key("a/").unwrap(), "tenant/a/");
+    assert!(scope.key("../other/a").is_err());
+    assert!(scope.key("/other/a").is_err());
+}
+
+#[test]
+fn invalid_prefixes_are_rejected() {
+    for prefix in ["", "/tenant", "../tenant", "tenant//a"] {
+        assert!(
+            ScopedStore::new(common::store(), prefix).is_err(),
+            "{prefix:?}"
+        );
+    }
+}
+
+#[test]
+fn rewrites_cannot_escape_the_scope() {
+    let escaping = scope().with_key_rewrite(|fragment| format!("../{fragment}"));
+    assert!(matches!(
+        escaping.key("a"),
+        Err(ScopeError::PathTraversal(_))
+    ));
+
+    let escaping = scope().with_key_rewrite(|fragment| format!("/{fragment}"));
+    assert!(matches!(escaping.key("a"), Err(ScopeError::AbsoluteKey(_))));
+
+    let escaping = scope().with_key_rewrite(|_| String::new());
+    assert!(matches!(escaping.key("a"), Err(ScopeError::EmptyKey)));
+
+    let escaping = scope().with_key_rewrite(|fragment| format!("shard-{fragment}"));
+    assert_eq!(escaping.key("a").unwrap(), "tenant/shard-a");
+}
+
+#[test]
+fn rewrites_apply_to_validated_fragments_only() {
+    let scope = scope().with_key_rewrite(|fragment| fragment.replace("..", "dots"));
+    assert!(matches!(
+        scope.key("../a"),
+        Err(ScopeError::PathTraversal(_))
+    ));
+}
+
+#[test]
+fn subscopes_nest() {
+    let sub = scope().subscope("a").unwrap();
+    assert_eq!(sub.prefix(), "tenant/a/");
+    assert_eq!(sub.key("b").unwrap(), "tenant/a/b");
+    assert_eq!(scope().subscope("a/").unwrap().prefix(), "tenant/a/");
+
+    let subsub = sub.subscope("b/c").unwrap();
+    assert_eq!(subsub.key("d").unwrap(), "tenant/a/b/c/d");
+    assert!(subsub.key("../d").is_err());
+}
+
+#[test]
+fn subscopes_are_validated() {
+    for fragment in ["", "..", "/a", "a//b", "a/../b"] {
+        assert!(scope().subscope(fragment).is_err(), "{fragment:?}");
+    }
+}
+
+#[test]
+fn subscopes_keep_the_rewrite() {
+    let sub = scope()
+        .with_key_rewrite(|fragment| format!("x-{fragment}"))
+        .subscope("a")
+        .unwrap();
+    assert_eq!(sub.prefix(), "tenant/x-a/");
+    assert_eq!(sub.key("b").unwrap(), "tenant/x-a/x-b");
+}
@@ -0,0 +1,119 @@
+mod common;
+
+use vl_aws_util::manifest::{Manifest, ShardEntry};
+use vl_aws_util::session::{DownloadCursor, DownloadSession, KeyProgress, SessionError};
+
+fn progress(key: &str, size: Option<usize>, bytes_read: usize) -> KeyProgress {
+    KeyProgress {
+        key: key.to_string(),
+        size,
+        bytes_read,
+        done: false,
+    }
+}
+
+fn cursor(keys: Vec<KeyProgress>) -> DownloadCursor {
+    DownloadCursor {
+        bucket: "bucket".to_string(),
+        chunk_size: 4,
+        keys,
+    }
+}
+
+#[test]
+fn cursors_round_trip() {
+    let mut cursor = cursor(vec![progress("a", Some(8), 8), progress("b", None, 4)]);
+    cursor.keys[0].done = true;
+
+    let json = serde_json::to_string(&cursor).unwrap();
+    let restored: DownloadCursor = serde_json::from_str(&json).unwrap();
+    assert_eq!(serde_json::to_string(&restored).unwrap(), json);
+    assert_eq!(restored.bytes_read(), 12);
+    assert!(!restored.is_done());
+}
+
+#[test]
+fn unaligned_sizes_are_rejected() {
+    assert!(
+        cursor(vec![progress("a", Some(8), 0), progress("b", None, 0)])
+            .validate()
+            .is_ok()
+    );
+
+    for size in [1, 3, 9] {
+        assert!(matches!(
+            cursor(vec![progress("a", Some(size), 0)]).validate(),
+            Err(SessionError::UnalignedSize { size: s, .. }) if s == size
+        ));
+    }
+
+    let mut zero = cursor(Vec::new());
+    zero.chunk_size = 0;
+    assert!(matches!(zero.validate(), Err(SessionError::ZeroChunkSize)));
+}
+
+#[tokio::test]
+async fn fully_read_keys_are_not_reopened() {
+    // nothing is left to read, so no request should be made
+    let cursor = cursor(vec![progress("a", Some(8), 8), progress("b", Some(0), 0)]);
+    let mut session = DownloadSession::resume(common::unreachable_client(), cursor).unwrap();
+
+    assert!(session.next().await.is_none());
+    assert!(session.cursor().is_done());
+}
+
+#[tokio::test]
+async fn failed_keys_resume_where_they_stopped() {
+    let cursor = cursor(vec![progress("a", Some(8), 8), progress("b", Some(8), 4)]);
+    let mut session = DownloadSession::resume(common::unreachable_client(), cursor).unwrap();
+
+    assert!(matches!(session.next().await, Some(Err(_))));
+    let cursor = session.cursor();
+    assert!(cursor.keys[0].done);
+    assert!(!cursor.keys[1].done);
+    assert_eq!(cursor.keys[1].bytes_read, 4);
+}
+
+#[test]
+fn invalid_cursors_are_not_resumed() {
+    let mut zero = cursor(vec![progress("a", Some(8), 0)]);
+    zero.chunk_size = 0;
+    assert!(matches!(
+        DownloadSession::resume(common::unreachable_client(), zero),
+        Err(SessionError::ZeroChunkSize)
+    ));
+
+    let unaligned = cursor(vec![progress("a", Some(5), 0)]);
+    assert!(matches!(
+        DownloadSession::resume(common::unreachable_client(), unaligned),
+        Err(SessionError::UnalignedSize { .. })
+    ));
+
+    let invalid_key = cursor(vec![progress("", Some(8), 0)]);
+    assert!(matches!(
+        DownloadSession::resume(common::unreachable_client(), invalid_key),
+        Err(SessionError::InvalidKey(_))
+    ));
+}
+
+#[test]
+fn manifests_without_a_chunk_size_are_rejected() {
+    let manifest = Manifest {
+        chunk_size: 0,
+        shards: vec![ShardEntry {
+            key: "a".to_string(),
+            start_index: 0,
+            count: 2,
+            stride: 1,
+        }],
+    };
+
+    assert!(matches!(
+        DownloadSession::from_manifest(
+            common::unreachable_client(),
+            "bucket".to_string(),
+            &manifest
+        ),
+        Err(SessionError::ZeroChunkSize)
+    ));
+}
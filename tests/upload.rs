@@ -0,0 +1,245 @@
+mod common;
+
+use std::{
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, SystemTime},
+};
+
+use aws_sdk_s3::primitives::SdkBody;
+use bytes::Bytes;
+use futures::future::poll_fn;
+use futures::task::noop_waker_ref;
+use vl_aws_util::upload::{
+    skew, ChecksumAlgorithm, PartId, ShardSize, Upload, UploadInfo, UploadInfoV1, UploadSendError,
+    Uploads,
+};
+
+const PART_SIZE: usize = 5 * 1024 * 1024;
+
+fn info(created_at: SystemTime) -> UploadInfo {
+    UploadInfoV1 {
+        version: 1,
+        bucket: "bucket".to_string(),
+        key: "key".to_string(),
+        size_per_upload: PART_SIZE,
+        upload_id: "upload".to_string(),
+        parts: Vec::new(),
+        uploaded_bytes: 0,
+        created_at: Some(created_at),
+        completed: None,
+    }
+    .try_into()
+    .unwrap()
+}
+
+#[test]
+fn polling_outside_a_runtime_fails_instead_of_panicking() {
+    let mut upload = Upload::new_from_info(common::client(), info(SystemTime::now()));
+    let mut cx = Context::from_waker(noop_waker_ref());
+
+    assert!(matches!(
+        upload.poll_send_ready(&mut cx),
+        Poll::Ready(Err(UploadSendError::NoRuntime))
+    ));
+}
+
+#[test]
+fn start_send_refuses_data_for_an_expiring_session() {
+    let created_at = SystemTime::now() - Duration::from_secs(3600);
+    let mut upload = Upload::new_from_info(common::client(), info(created_at))
+        .with_max_session_duration(Duration::from_secs(60));
+
+    assert!(matches!(
+        upload.start_send(Bytes::from_static(b"data")),
+        Err(UploadSendError::RefreshPending)
+    ));
+    assert_eq!(upload.info.uploaded_bytes, 0);
+}
+
+#[test]
+fn start_send_buffers_data_for_a_fresh_session() {
+    let mut upload = Upload::new_from_info(common::client(), info(SystemTime::now()))
+        .with_max_session_duration(Duration::from_secs(60));
+
+    upload.start_send(Bytes::from_static(b"data")).unwrap();
+    assert_eq!(upload.size().buffered, 4);
+}
+
+#[test]
+fn start_send_refuses_data_for_a_session_of_unknown_age() {
+    let mut info = serde_json::to_value(info(SystemTime::now())).unwrap();
+    info["created_at"] = serde_json::Value::Null;
+    let info: UploadInfo = serde_json::from_value(info).unwrap();
+    let mut upload = Upload::new_from_info(common::client(), info)
+        .with_max_session_duration(Duration::from_secs(60));
+
+    assert!(matches!(
+        upload.start_send(Bytes::from_static(b"data")),
+        Err(UploadSendError::RefreshPending)
+    ));
+}
+
+#[test]
+fn start_send_refuses_data_for_a_completed_session() {
+    let mut info = serde_json::to_value(info(SystemTime::now())).unwrap();
+    info["completed"] = serde_json::json!({ "e_tag": "\"etag\"" });
+    let info: UploadInfo = serde_json::from_value(info).unwrap();
+    let mut upload = Upload::new_from_info(common::client(), info);
+
+    assert!(matches!(
+        upload.start_send(Bytes::from_static(b"data")),
+        Err(UploadSendError::RefreshPending)
+    ));
+}
+
+#[test]
+fn completed_sessions_are_only_serialized_while_set() {
+    let info = info(SystemTime::now());
+    let json = serde_json::to_value(&info).unwrap();
+    assert!(json.get("completed").is_none());
+}
+
+fn shard(total: usize) -> ShardSize {
+    ShardSize {
+        buffered: 0,
+        uploaded: total,
+    }
+}
+
+#[test]
+fn equal_shards_have_no_skew() {
+    assert_eq!(skew(&[shard(10), shard(10), shard(10)]), 1.0);
+}
+
+#[test]
+fn empty_shards_have_no_skew() {
+    assert_eq!(skew(&[]), 1.0);
+    assert_eq!(skew(&[shard(0), shard(0)]), 1.0);
+}
+
+#[test]
+fn a_hot_shard_is_skewed() {
+    // a mean of 10, with the largest shard at 37
+    assert_eq!(skew(&[shard(1), shard(1), shard(1), shard(37)]), 3.7);
+}
+
+#[tokio::test]
+async fn sizes_are_read_while_a_part_is_in_flight() {
+    let upload = Upload::new_from_info(common::hanging_client(), info(SystemTime::now()));
+    let uploads = Arc::new(Uploads::from_uploads(vec![upload]));
+    let part = Bytes::from(vec![0; PART_SIZE]);
+    // the first part starts uploading, the second waits for it while
+    // holding the upload's lock
+    uploads.send(0, part.clone()).await.unwrap();
+    let sending = tokio::spawn({
+        let uploads = uploads.clone();
+        async move { uploads.send(0, part).await }
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    assert_eq!(
+        uploads.sizes(),
+        vec![ShardSize {
+            buffered: 2 * PART_SIZE,
+            uploaded: 0
+        }]
+    );
+    assert!(!sending.is_finished());
+    sending.abort();
+}
+
+/// Upload a single part to a store answering with `headers`, returning
+/// the id it was recorded with.
+async fn upload_part(
+    headers: &'static [(&'static str, &'static str)],
+) -> Result<PartId, UploadSendError> {
+    let (client, _) = common::responding_client(move |_| {
+        let mut response = http::Response::builder().status(200);
+        for (name, value) in headers {
+            response = response.header(*name, *value);
+        }
+        response.body(SdkBody::empty()).unwrap()
+    });
+    let mut upload = Upload::new_from_info(client, info(SystemTime::now()));
+    upload.send(Bytes::from(vec![0; PART_SIZE])).await.unwrap();
+    poll_fn(|cx| upload.poll_part_complete(cx)).await?;
+
+    let mut parts = UploadInfoV1::from(upload.info.clone()).parts;
+    assert_eq!(parts.len(), 1);
+    Ok(parts.remove(0))
+}
+
+#[tokio::test]
+async fn parts_are_identified_by_their_etag_first() {
+    let part = upload_part(&[("etag", "\"etag\""), ("x-amz-checksum-crc32", "AAAAAA==")]).await;
+
+    assert_eq!(part.unwrap(), PartId::ETag("\"etag\"".to_string()));
+}
+
+#[tokio::test]
+async fn parts_without_an_etag_are_identified_by_their_checksum() {
+    let part = upload_part(&[("x-amz-checksum-crc32c", "AAAAAA==")]).await;
+
+    assert_eq!(
+        part.unwrap(),
+        PartId::Checksum {
+            algorithm: ChecksumAlgorithm::Crc32C,
+            checksum: "AAAAAA==".to_string(),
+        }
+    );
+}
+
+#[tokio::test]
+async fn parts_without_any_id_are_an_error() {
+    let part = upload_part(&[]).await;
+
+    assert!(matches!(part, Err(UploadSendError::NoPartId(1))));
+}
+
+#[tokio::test]
+async fn checksums_are_sent_when_completing() {
+    let completion = Arc::new(Mutex::new(None));
+    let captured = completion.clone();
+    let (client, _) = common::responding_client(move |request| {
+        let response = http::Response::builder().status(200);
+        if request.method() == http::Method::POST {
+            let body = request.body().bytes().unwrap().to_vec();
+            *captured.lock().unwrap() = Some(String::from_utf8(body).unwrap());
+            response
+                .body(SdkBody::from("<CompleteMultipartUploadResult/>"))
+                .unwrap()
+        } else {
+            response
+                .header("x-amz-checksum-sha256", "c2hhMjU2")
+                .body(SdkBody::empty())
+                .unwrap()
+        }
+    });
+    let mut upload = Upload::new_from_info(client, info(SystemTime::now()));
+    upload.send(Bytes::from(vec![0; PART_SIZE])).await.unwrap();
+    upload.complete().await.unwrap();
+
+    let completion = completion.lock().unwrap().clone().unwrap();
+    assert!(completion.contains("<ChecksumSHA256>c2hhMjU2</ChecksumSHA256>"));
+    assert!(!completion.contains("<ETag>"));
+}
+
+#[test]
+fn part_ids_round_trip() {
+    let checksum = PartId::Checksum {
+        algorithm: ChecksumAlgorithm::Crc64Nvme,
+        checksum: "AAAAAAAAAAA=".to_string(),
+    };
+    let json = serde_json::to_string(&checksum).unwrap();
+    assert_eq!(
+        json,
+        r#"{"algorithm":"crc64_nvme","checksum":"AAAAAAAAAAA="}"#
+    );
+    assert_eq!(serde_json::from_str::<PartId>(&json).unwrap(), checksum);
+
+    let e_tag = PartId::ETag("\"etag\"".to_string());
+    let json = serde_json::to_string(&e_tag).unwrap();
+    assert_eq!(json, r#""\"etag\"""#);
+    assert_eq!(serde_json::from_str::<PartId>(&json).unwrap(), e_tag);
+}